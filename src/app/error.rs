@@ -1,12 +1,32 @@
 use std::fmt::Display;
 
 
+/// Everything that can go wrong in a request or download.
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     UrlParsingError,
     IoError(String),
     Utf8ParsingError,
-    HeaderParsingError
+    HeaderParsingError,
+    RangeNotSupported,
+    ResourceChanged,
+    ChecksumParsingError,
+    ChecksumMismatch { expected: String, actual: String },
+    QueueParsingError,
+    UnknownQueueItem,
+    QueueItemRunning,
+    Cancelled,
+    Timeout,
+    TooManyRedirects,
+    HttpStatus(usize),
+    MethodParsingError,
+    ChunkParsingError,
+    CookieParsingError,
+    NetrcParsingError,
+    ProxyError(String),
+    TlsError(String),
+    ResolveError(String)
 }
 
 impl Display for Error {
@@ -16,10 +36,30 @@ impl Display for Error {
             Error::IoError(e) => write!(f, "An error occurred on IO operation: {}", e),
             Error::Utf8ParsingError => write!(f, "An error occurred on parsing response!"),
             Error::HeaderParsingError => write!(f, "An error occurred on parsing headers of the response!"),
+            Error::RangeNotSupported => write!(f, "The server did not honour the requested byte range!"),
+            Error::ResourceChanged => write!(f, "The remote file changed while it was being downloaded!"),
+            Error::ChecksumParsingError => write!(f, "An error occurred on parsing the checksum!"),
+            Error::QueueParsingError => write!(f, "An error occurred on parsing the download queue!"),
+            Error::UnknownQueueItem => write!(f, "There is no such item in the download queue!"),
+            Error::QueueItemRunning => write!(f, "The item of the download queue is still running!"),
+            Error::Cancelled => write!(f, "The operation was cancelled!"),
+            Error::Timeout => write!(f, "The operation timed out!"),
+            Error::TooManyRedirects => write!(f, "The server redirected too many times!"),
+            Error::HttpStatus(status_code) => write!(f, "The server answered with status {}!", status_code),
+            Error::MethodParsingError => write!(f, "An error occurred on parsing the method!"),
+            Error::ChunkParsingError => write!(f, "An error occurred on parsing a chunk of the response body!"),
+            Error::CookieParsingError => write!(f, "An error occurred on parsing the cookie file!"),
+            Error::NetrcParsingError => write!(f, "An error occurred on parsing the netrc file!"),
+            Error::ProxyError(e) => write!(f, "An error occurred on the proxy: {}", e),
+            Error::TlsError(e) => write!(f, "An error occurred on TLS: {}", e),
+            Error::ResolveError(e) => write!(f, "An error occurred on resolving the host: {}", e),
+            Error::ChecksumMismatch { expected, actual } => write!(f, "The checksum of the downloaded file does not match! expected {}, got {}", expected, actual),
         }
     }
 }
 
+impl std::error::Error for Error {}
+
 impl From<std::io::Error> for Error {
     fn from(e: std::io::Error) -> Self {
         Error::IoError(e.to_string())
@@ -0,0 +1,331 @@
+use std::{path::PathBuf, time::Duration};
+
+use clap::{Args, Parser, Subcommand};
+
+use http_client::client::method::Method;
+
+/// Sends HTTP requests, with flags modelled after curl, and downloads files.
+#[derive(Parser, Debug)]
+#[command(name = "http_client", version, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub request: Option<RequestArgs>
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Downloads files over several connections each, many at a time.
+    Download(DownloadArgs)
+}
+
+#[derive(Args, Debug)]
+pub struct RequestArgs {
+    pub url: String,
+
+    /// Request method, defaults to GET, or POST when data is sent.
+    #[arg(short = 'X', long = "request", value_name = "METHOD")]
+    pub method: Option<Method>,
+
+    /// Extra header as "Name: value", may be repeated.
+    #[arg(short = 'H', long = "header", value_name = "HEADER")]
+    pub headers: Vec<String>,
+
+    /// Form data to POST; "@file" reads it from a file without line breaks.
+    /// Repeated values are joined with "&".
+    #[arg(short = 'd', long = "data", value_name = "DATA")]
+    pub data: Vec<String>,
+
+    /// Like --data, but "@file" is sent exactly as it is.
+    #[arg(long = "data-binary", value_name = "DATA")]
+    pub data_binary: Vec<String>,
+
+    /// Multipart field as "name=value", or "name=@file" to upload a file.
+    #[arg(short = 'F', long = "form", value_name = "FIELD")]
+    pub form: Vec<String>,
+
+    /// JSON to POST; "@file" reads it from a file.
+    #[arg(long, value_name = "DATA")]
+    pub json: Vec<String>,
+
+    /// Write the body to this file, or into this directory under the name
+    /// the server suggests.
+    #[arg(short = 'o', long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+
+    /// Write the response headers before the body.
+    #[arg(short = 'i', long)]
+    pub include: bool,
+
+    /// Only fetch the headers, with a HEAD request.
+    #[arg(short = 'I', long)]
+    pub head: bool,
+
+    /// Follow redirects.
+    #[arg(short = 'L', long)]
+    pub location: bool,
+
+    #[arg(long, value_name = "NUM", default_value_t = 50)]
+    pub max_redirs: usize,
+
+    /// Print the request and response heads to stderr.
+    #[arg(short = 'v', long)]
+    pub verbose: bool,
+
+    /// Credentials to answer a Basic or Digest challenge with. They are
+    /// not sent to other hosts after a redirect.
+    #[arg(short = 'u', long, value_name = "USER[:PASSWORD]")]
+    pub user: Option<String>,
+
+    #[arg(long, value_name = "TOKEN")]
+    pub oauth2_bearer: Option<String>,
+
+    /// Take credentials from ~/.netrc.
+    #[arg(short = 'n', long)]
+    pub netrc: bool,
+
+    /// Take credentials from this netrc file.
+    #[arg(long, value_name = "FILE")]
+    pub netrc_file: Option<PathBuf>,
+
+    /// Proxy for every request, instead of the one in http_proxy,
+    /// https_proxy or all_proxy. socks5h:// lets the proxy resolve names.
+    #[arg(short = 'x', long, value_name = "[SCHEME://]HOST[:PORT]")]
+    pub proxy: Option<String>,
+
+    #[arg(short = 'U', long, value_name = "USER[:PASSWORD]")]
+    pub proxy_user: Option<String>,
+
+    /// Hosts to reach without the proxy, instead of no_proxy.
+    #[arg(long, value_name = "HOSTS")]
+    pub noproxy: Option<String>,
+
+    /// Cookies to send as "name=value; name2=value2", or a cookie file to
+    /// read them from.
+    #[arg(short = 'b', long, value_name = "DATA|FILE")]
+    pub cookie: Vec<String>,
+
+    /// Write the cookies to a file in the Netscape format afterwards.
+    #[arg(short = 'c', long, value_name = "FILE")]
+    pub cookie_jar: Option<PathBuf>,
+
+    /// Connect to these addresses for HOST on PORT, or on any port with
+    /// '*', instead of resolving it.
+    #[arg(long, value_name = "HOST:PORT:ADDR[,ADDR]...")]
+    pub resolve: Vec<String>,
+
+    /// Connect over IPv4 only.
+    #[arg(short = '4', long, conflicts_with = "ipv6")]
+    pub ipv4: bool,
+
+    /// Connect over IPv6 only.
+    #[arg(short = '6', long)]
+    pub ipv6: bool,
+
+    /// Trust the CA certificates of this PEM file instead of the bundled
+    /// Mozilla roots.
+    #[arg(long, value_name = "FILE")]
+    pub cacert: Vec<PathBuf>,
+
+    /// Trust the CA certificates of the operating system too.
+    #[arg(long)]
+    pub ca_native: bool,
+
+    /// Client certificate in PEM, followed by its key unless --key is given.
+    #[arg(short = 'E', long, value_name = "FILE")]
+    pub cert: Option<PathBuf>,
+
+    /// Private key of the client certificate in PEM.
+    #[arg(long, value_name = "FILE", requires = "cert")]
+    pub key: Option<PathBuf>,
+
+    /// Accept the server only with one of these public key hashes
+    /// (sha256//<base64>) or certificate fingerprints, separated by ';'.
+    #[arg(long, value_name = "HASHES")]
+    pub pinnedpubkey: Option<String>,
+
+    /// Do not verify the certificate of the server.
+    #[arg(short = 'k', long)]
+    pub insecure: bool,
+
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub connect_timeout: Option<Duration>,
+
+    /// Limit for the whole operation.
+    #[arg(short = 'm', long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub max_time: Option<Duration>,
+
+    /// Fail without writing the body when the status is 400 or above.
+    #[arg(short = 'f', long)]
+    pub fail: bool,
+
+    /// Do not print errors.
+    #[arg(short = 's', long)]
+    pub silent: bool
+}
+
+#[derive(Args, Debug)]
+pub struct DownloadArgs {
+    pub urls: Vec<String>,
+
+    /// File with one URL per line, optionally followed by the expected
+    /// checksum as "algorithm:hex". Empty lines and lines starting with "#"
+    /// are skipped.
+    #[arg(short = 'i', long, value_name = "FILE")]
+    pub input_file: Option<PathBuf>,
+
+    #[arg(short = 'o', long, value_name = "DIR", default_value = ".")]
+    pub output_dir: PathBuf,
+
+    /// Connections per download.
+    #[arg(short = 'c', long, value_name = "NUM", default_value_t = 5)]
+    pub connections: usize,
+
+    /// Initial segment size, e.g. 500k or 4M.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "500k")]
+    pub segment_size: usize,
+
+    /// Continue interrupted downloads.
+    #[arg(short = 'r', long)]
+    pub resume: bool,
+
+    /// Expected checksum as "algorithm:hex" of the URL on the command line.
+    /// Checksums of several URLs are given with --input-file.
+    #[cfg(feature = "checksum")]
+    #[arg(long, value_name = "CHECKSUM")]
+    pub checksum: Vec<String>,
+
+    /// Limit of all downloads together in bytes per second, e.g. 2M.
+    #[arg(long, value_name = "RATE", value_parser = parse_size)]
+    pub limit_rate: Option<usize>,
+
+    /// Downloads running at the same time.
+    #[arg(short = 'j', long, value_name = "NUM", default_value_t = 3)]
+    pub parallel: usize,
+
+    /// Attempts for every failed segment.
+    #[arg(long, value_name = "NUM", default_value_t = 3)]
+    pub retries: usize,
+
+    /// Take credentials from ~/.netrc.
+    #[arg(long)]
+    pub netrc: bool,
+
+    /// Take credentials from this netrc file.
+    #[arg(long, value_name = "FILE")]
+    pub netrc_file: Option<PathBuf>,
+
+    /// Proxy for every download, instead of the one in http_proxy,
+    /// https_proxy or all_proxy. socks5h:// lets the proxy resolve names.
+    #[arg(short = 'x', long, value_name = "[SCHEME://]HOST[:PORT]")]
+    pub proxy: Option<String>,
+
+    #[arg(long, value_name = "USER[:PASSWORD]")]
+    pub proxy_user: Option<String>,
+
+    /// Hosts to reach without the proxy, instead of no_proxy.
+    #[arg(long, value_name = "HOSTS")]
+    pub noproxy: Option<String>,
+
+    /// Cookie file in the Netscape format to send cookies from.
+    #[arg(long, value_name = "FILE")]
+    pub load_cookies: Option<PathBuf>,
+
+    /// Write the cookies to a file in the Netscape format afterwards.
+    #[arg(long, value_name = "FILE")]
+    pub save_cookies: Option<PathBuf>,
+
+    /// Connect to these addresses for HOST on PORT, or on any port with
+    /// '*', instead of resolving it.
+    #[arg(long, value_name = "HOST:PORT:ADDR[,ADDR]...")]
+    pub resolve: Vec<String>,
+
+    /// Connect over IPv4 only.
+    #[arg(short = '4', long, conflicts_with = "ipv6")]
+    pub ipv4: bool,
+
+    /// Connect over IPv6 only.
+    #[arg(short = '6', long)]
+    pub ipv6: bool,
+
+    /// Trust the CA certificates of this PEM file instead of the bundled
+    /// Mozilla roots.
+    #[arg(long, value_name = "FILE")]
+    pub cacert: Vec<PathBuf>,
+
+    /// Trust the CA certificates of the operating system too.
+    #[arg(long)]
+    pub ca_native: bool,
+
+    /// Client certificate in PEM, followed by its key unless --key is given.
+    #[arg(short = 'E', long, value_name = "FILE")]
+    pub cert: Option<PathBuf>,
+
+    /// Private key of the client certificate in PEM.
+    #[arg(long, value_name = "FILE", requires = "cert")]
+    pub key: Option<PathBuf>,
+
+    /// Accept the server only with one of these public key hashes
+    /// (sha256//<base64>) or certificate fingerprints, separated by ';'.
+    #[arg(long, value_name = "HASHES")]
+    pub pinnedpubkey: Option<String>,
+
+    /// Do not verify the certificate of the server.
+    #[arg(short = 'k', long)]
+    pub insecure: bool,
+
+    /// Do a full TLS handshake on every connection instead of resuming
+    /// sessions.
+    #[arg(long)]
+    pub no_sessionid: bool,
+
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub connect_timeout: Option<Duration>,
+
+    /// Print how many TLS handshakes were made and resumed at the end.
+    #[arg(short = 'v', long)]
+    pub verbose: bool,
+
+    /// Print nothing but errors.
+    #[arg(short = 's', long)]
+    pub silent: bool
+}
+
+/// Parses a byte count with an optional binary suffix: k, M or G.
+fn parse_size(value: &str) -> Result<usize, String> {
+    let trimmed = value.trim();
+    let (number, multiplier) = match trimmed.chars().last().map(|c| c.to_ascii_lowercase()) {
+        Some('k') => (&trimmed[..trimmed.len() - 1], 1024.0),
+        Some('m') => (&trimmed[..trimmed.len() - 1], 1024.0 * 1024.0),
+        Some('g') => (&trimmed[..trimmed.len() - 1], 1024.0 * 1024.0 * 1024.0),
+        _ => (trimmed, 1.0),
+    };
+
+    number.parse::<f64>().ok()
+        .filter(|n| *n >= 0.0)
+        .map(|n| (n * multiplier) as usize)
+        .ok_or_else(|| format!("`{}` is not a size", value))
+}
+
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    value.parse::<f64>().ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(|| format!("`{}` is not a number of seconds", value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_size;
+
+    #[test]
+    fn sizes_with_suffixes() {
+        assert_eq!(parse_size("500"), Ok(500));
+        assert_eq!(parse_size("500k"), Ok(512_000));
+        assert_eq!(parse_size("1.5M"), Ok(1_572_864));
+        assert_eq!(parse_size("2g"), Ok(2_147_483_648));
+        assert!(parse_size("fast").is_err());
+    }
+
+}
@@ -0,0 +1,20 @@
+use std::path::Path;
+
+use http_client::client::CookieJar;
+use super::exit::Failure;
+
+/// A cookie jar holding the cookies of `files`.
+pub async fn open(files: &[&Path]) -> Result<CookieJar, Failure> {
+    let jar = CookieJar::new();
+    for file in files {
+        jar.load(file).await.map_err(|e| match e {
+            http_client::Error::IoError(_) => Failure::read(e),
+            e => e.into(),
+        })?;
+    }
+    Ok(jar)
+}
+
+pub async fn save(jar: &CookieJar, file: &Path) -> Result<(), Failure> {
+    jar.save(file).await.map_err(Failure::write)
+}
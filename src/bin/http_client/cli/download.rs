@@ -0,0 +1,211 @@
+use std::{io::IsTerminal, path::{Path, PathBuf}, sync::Arc, time::{Duration, Instant}};
+
+use tokio::{sync::Semaphore, task::JoinSet};
+use tokio_util::sync::CancellationToken;
+
+use http_client::Error;
+use http_client::client::{client::Client, throttle::RateLimiter};
+#[cfg(feature = "checksum")]
+use http_client::download::checksum::Checksum;
+use http_client::download::{options::DownloadOptions, progress::Progress};
+use super::{args::DownloadArgs, cookies, exit::{exit_code, Failure}, netrc, progress_bar::ProgressBars, proxy, resolve, tls};
+
+/// How often the progress bars are redrawn.
+static REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A URL to download and the checksums it is expected to have.
+#[derive(Debug, PartialEq)]
+struct Job {
+    url: String,
+    checksums: Vec<String>
+}
+
+/// Downloads every URL on the command line and in the input file, up to
+/// `--parallel` at a time. Ctrl-C stops them all; with `--resume` they
+/// continue from there on the next run.
+pub async fn run(args: &DownloadArgs) -> Result<(), Failure> {
+    let jobs = jobs(args).await?;
+    if jobs.is_empty() {
+        return Err(Failure::usage("no URL to download was given".to_string()));
+    }
+    tokio::fs::create_dir_all(&args.output_dir).await.map_err(|e| Failure::write(e.into()))?;
+
+    let jar = match (&args.load_cookies, &args.save_cookies) {
+        (None, None) => None,
+        (load, _) => Some(cookies::open(load.as_deref().as_slice()).await?),
+    };
+
+    let mut client = Client::new();
+    if let Some(timeout) = args.connect_timeout {
+        client = client.set_connect_timeout(timeout);
+    }
+    if let Some(jar) = &jar {
+        client = client.set_cookie_jar(jar.clone());
+    }
+    if let Some(netrc) = netrc::open(args.netrc, args.netrc_file.as_deref()).await? {
+        client = client.set_netrc(netrc);
+    }
+    client = client.set_resolver(resolve::resolver(&args.resolve, args.ipv4, args.ipv6)?);
+    if let Some(proxies) = proxy::proxies(args.proxy.as_deref(), args.proxy_user.as_deref(), args.noproxy.as_deref())? {
+        client = client.set_proxies(proxies);
+    }
+    let mut tls_config = tls::config(
+        &args.cacert, args.ca_native, args.cert.as_deref(), args.key.as_deref(), args.pinnedpubkey.as_deref(), args.insecure
+    ).await?;
+    if args.no_sessionid {
+        tls_config = tls_config.set_session_cache_size(0);
+    }
+    client = client.set_tls_config(tls_config);
+
+    let cancellation = CancellationToken::new();
+    let mut options = DownloadOptions::new()
+        .set_connection_count(args.connections.max(1))
+        .set_segment_size(args.segment_size.max(1))
+        .set_resume(args.resume)
+        .set_retries(args.retries)
+        .set_cancellation(cancellation.clone());
+    if let Some(rate) = args.limit_rate {
+        options = options.set_rate_limiter(RateLimiter::new(rate));
+    }
+
+    let mut bars = ProgressBars::new(std::io::stderr().is_terminal(), args.silent);
+    let permits = Arc::new(Semaphore::new(args.parallel.max(1)));
+    let mut tasks = JoinSet::new();
+    let count = jobs.len();
+
+    for (index, job) in jobs.into_iter().enumerate() {
+        let progress = Progress::new();
+        let options = job_options(options.clone(), &job)?.set_progress(progress.clone());
+        bars.add(&job.url, progress);
+
+        let (client, permits, output_dir) = (client.clone(), permits.clone(), args.output_dir.clone());
+        tasks.spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            let started = Instant::now();
+            let result = download(&client, &job.url, &output_dir, options).await;
+            (index, result, started.elapsed())
+        });
+    }
+
+    let mut redraw = tokio::time::interval(REDRAW_INTERVAL);
+    let mut failures = vec![];
+    loop {
+        tokio::select! {
+            joined = tasks.join_next() => match joined {
+                Some(Ok((index, result, elapsed))) => {
+                    bars.finish(index, &result, elapsed);
+                    if let Err(e) = result {
+                        failures.push(e);
+                    }
+                },
+                Some(Err(e)) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+                Some(Err(_)) => {},
+                None => break,
+            },
+            _ = tokio::signal::ctrl_c(), if !cancellation.is_cancelled() => cancellation.cancel(),
+            _ = redraw.tick() => bars.draw(),
+        }
+    }
+
+    if let (Some(jar), Some(file)) = (&jar, &args.save_cookies) {
+        cookies::save(jar, file).await?;
+    }
+    if args.verbose {
+        let stats = client.tls_stats();
+        eprintln!("TLS handshakes: {}, {} of them resumed", stats.handshakes, stats.resumed);
+    }
+
+    match failures.first() {
+        None => Ok(()),
+        Some(first) => Err(Failure {
+            code: exit_code(first),
+            message: format!("{} of {} downloads failed", failures.len(), count)
+        }),
+    }
+}
+
+async fn download(client: &Client, url: &str, output_dir: &Path, options: DownloadOptions) -> Result<PathBuf, Error> {
+    let mut connection = client.connect(url).await?;
+    connection.download_with(output_dir, options).await
+}
+
+#[cfg(feature = "checksum")]
+fn job_options(mut options: DownloadOptions, job: &Job) -> Result<DownloadOptions, Failure> {
+    for checksum in &job.checksums {
+        options = options.set_checksum(Checksum::parse(checksum)?);
+    }
+    Ok(options)
+}
+
+#[cfg(not(feature = "checksum"))]
+fn job_options(options: DownloadOptions, job: &Job) -> Result<DownloadOptions, Failure> {
+    if job.checksums.is_empty() {
+        Ok(options)
+    }else {
+        Err(Failure::usage("checksums need the `checksum` feature".to_string()))
+    }
+}
+
+async fn jobs(args: &DownloadArgs) -> Result<Vec<Job>, Failure> {
+    #[cfg(feature = "checksum")]
+    let checksums = args.checksum.clone();
+    #[cfg(not(feature = "checksum"))]
+    let checksums: Vec<String> = vec![];
+
+    let mut jobs = command_line_jobs(&args.urls, &checksums)?;
+    if let Some(path) = &args.input_file {
+        let list = tokio::fs::read_to_string(path).await.map_err(|e| Failure::read(e.into()))?;
+        jobs.extend(parse_list(&list));
+    }
+    Ok(jobs)
+}
+
+/// The URLs given as arguments. A checksum can only belong to a single one,
+/// so it is refused along with several.
+fn command_line_jobs(urls: &[String], checksums: &[String]) -> Result<Vec<Job>, Failure> {
+    if urls.len() > 1 && !checksums.is_empty() {
+        return Err(Failure::usage("--checksum takes a single URL, give the checksums of several in --input-file".to_string()));
+    }
+    Ok(urls.iter().map(|url| Job { url: url.clone(), checksums: checksums.to_vec() }).collect())
+}
+
+/// Reads one URL per line, optionally followed by its checksum.
+fn parse_list(list: &str) -> Vec<Job> {
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut fields = line.split_whitespace();
+            let url = fields.next().unwrap_or_default().to_string();
+            Job { url, checksums: fields.map(str::to_string).collect() }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{command_line_jobs, parse_list, Job};
+
+    #[test]
+    fn list_skips_comments_and_reads_checksums() {
+        let list = "# mirrors\nhttps://a.example/one.iso sha256:00ff\n\n  https://b.example/two.zip  \n";
+        assert_eq!(parse_list(list), vec![
+            Job { url: "https://a.example/one.iso".to_string(), checksums: vec!["sha256:00ff".to_string()] },
+            Job { url: "https://b.example/two.zip".to_string(), checksums: vec![] },
+        ]);
+    }
+
+    #[test]
+    fn checksums_need_a_single_url() {
+        let checksums = vec!["sha256:00ff".to_string()];
+        let one = vec!["https://a.example/one.iso".to_string()];
+        let two = vec!["https://a.example/one.iso".to_string(), "https://b.example/two.zip".to_string()];
+
+        assert_eq!(command_line_jobs(&one, &checksums).unwrap(), vec![
+            Job { url: "https://a.example/one.iso".to_string(), checksums: checksums.clone() },
+        ]);
+        assert!(command_line_jobs(&two, &checksums).is_err());
+        assert_eq!(command_line_jobs(&two, &[]).unwrap().len(), 2);
+    }
+
+}
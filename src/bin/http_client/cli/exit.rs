@@ -0,0 +1,63 @@
+use http_client::Error;
+
+/// Why the CLI stops with an error, and the code it exits with.
+#[derive(Debug)]
+pub struct Failure {
+    pub code: u8,
+    pub message: String
+}
+
+impl Failure {
+
+    /// The command line asks for something impossible.
+    pub fn usage(message: String) -> Failure {
+        Failure { code: 2, message }
+    }
+
+    /// Writing the output failed, as opposed to the request.
+    pub fn write(error: Error) -> Failure {
+        Failure { code: 23, message: error.to_string() }
+    }
+
+    /// Reading a file named on the command line failed.
+    pub fn read(error: Error) -> Failure {
+        Failure { code: 26, message: error.to_string() }
+    }
+
+    pub fn http(status_code: usize) -> Failure {
+        Failure { code: 22, message: format!("The requested URL returned error: {}", status_code) }
+    }
+
+}
+
+impl From<Error> for Failure {
+    fn from(error: Error) -> Self {
+        Failure { code: exit_code(&error), message: error.to_string() }
+    }
+}
+
+/// The exit code for `error`. Errors curl knows use curl's code, the others
+/// get codes from 100 on.
+pub fn exit_code(error: &Error) -> u8 {
+    match error {
+        Error::MethodParsingError | Error::ChecksumParsingError => 2,
+        Error::UrlParsingError => 3,
+        Error::ResolveError(_) => 6,
+        Error::Utf8ParsingError | Error::HeaderParsingError | Error::ChunkParsingError => 8,
+        Error::Timeout => 28,
+        Error::RangeNotSupported => 33,
+        Error::Cancelled => 42,
+        Error::TooManyRedirects => 47,
+        Error::HttpStatus(_) => 22,
+        Error::ProxyError(_) => 97,
+        Error::TlsError(_) => 58,
+        Error::IoError(_) => 56,
+        Error::ChecksumMismatch { .. } => 100,
+        Error::ResourceChanged => 101,
+        Error::QueueParsingError => 102,
+        Error::UnknownQueueItem => 103,
+        Error::CookieParsingError => 104,
+        Error::NetrcParsingError => 105,
+        Error::QueueItemRunning => 106,
+    }
+}
@@ -0,0 +1,10 @@
+pub mod args;
+pub mod cookies;
+pub mod download;
+pub mod exit;
+pub mod netrc;
+pub mod progress_bar;
+pub mod proxy;
+pub mod request;
+pub mod resolve;
+pub mod tls;
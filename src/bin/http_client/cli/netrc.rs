@@ -0,0 +1,21 @@
+use std::path::Path;
+
+use http_client::client::netrc::Netrc;
+use super::exit::Failure;
+
+/// The netrc file to take credentials from: `file`, or the one in the home
+/// directory when only `--netrc` is given.
+pub async fn open(netrc: bool, file: Option<&Path>) -> Result<Option<Netrc>, Failure> {
+    let path = match file {
+        Some(file) => file.to_path_buf(),
+        None if netrc => Netrc::default_path()
+            .ok_or_else(|| Failure::usage("there is no home directory to find .netrc in".to_string()))?,
+        None => return Ok(None),
+    };
+
+    match Netrc::load(&path).await {
+        Ok(netrc) => Ok(Some(netrc)),
+        Err(e @ http_client::Error::IoError(_)) => Err(Failure::read(e)),
+        Err(e) => Err(e.into()),
+    }
+}
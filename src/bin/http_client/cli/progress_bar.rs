@@ -0,0 +1,177 @@
+use std::{io::Write, path::PathBuf, time::Duration};
+
+use http_client::Error;
+use http_client::download::progress::{Progress, ProgressSnapshot};
+
+static BAR_WIDTH: usize = 24;
+static SEGMENT_BAR_WIDTH: usize = 6;
+static NAME_WIDTH: usize = 28;
+
+struct Entry {
+    url: String,
+    progress: Progress,
+    done: bool
+}
+
+/// Draws a progress bar for every running download on stderr and a line for
+/// every finished one above them.
+///
+/// Without a terminal nothing is redrawn, only the finished lines are
+/// printed. Failures are printed even when silent.
+pub struct ProgressBars {
+    live: bool,
+    silent: bool,
+    entries: Vec<Entry>,
+    /// Lines drawn by the last `draw`, erased before drawing again.
+    drawn: usize
+}
+
+impl ProgressBars {
+
+    pub fn new(live: bool, silent: bool) -> ProgressBars {
+        ProgressBars { live: live && !silent, silent, entries: vec![], drawn: 0 }
+    }
+
+    pub fn add(&mut self, url: &str, progress: Progress) {
+        self.entries.push(Entry { url: url.to_string(), progress, done: false });
+    }
+
+    /// Reports the download at `index`, which took `elapsed` once it left the
+    /// queue.
+    pub fn finish(&mut self, index: usize, result: &Result<PathBuf, Error>, elapsed: Duration) {
+        let entry = &mut self.entries[index];
+        entry.done = true;
+
+        let line = match result {
+            Ok(_) if self.silent => return,
+            Ok(path) => {
+                let snapshot = entry.progress.snapshot();
+                let speed = snapshot.downloaded as f64 / elapsed.as_secs_f64().max(0.001);
+                format!("done    {}  {} in {} ({}/s)",
+                    path.display(), format_bytes(snapshot.downloaded), format_duration(elapsed), format_bytes(speed as usize))
+            },
+            Err(e) => format!("failed  {}  {}", entry.url, e),
+        };
+
+        let mut stderr = std::io::stderr().lock();
+        let _ = writeln!(stderr, "{}{}", self.erase(), line);
+        let _ = stderr.flush();
+        self.drawn = 0;
+    }
+
+    pub fn draw(&mut self) {
+        if !self.live {
+            return;
+        }
+
+        let mut screen = self.erase();
+        self.drawn = 0;
+        for entry in self.entries.iter().filter(|entry| !entry.done) {
+            let snapshot = entry.progress.snapshot();
+            // Queued downloads, and ones still asking for the file, have
+            // nothing to show yet.
+            let Some(file) = &snapshot.file else { continue };
+            let name = file.file_name().unwrap_or_default().to_string_lossy();
+            screen.push_str(&download_line(&name, &snapshot));
+            screen.push('\n');
+            screen.push_str(&segments_line(&snapshot));
+            screen.push('\n');
+            self.drawn += 2;
+        }
+
+        let mut stderr = std::io::stderr().lock();
+        let _ = stderr.write_all(screen.as_bytes());
+        let _ = stderr.flush();
+    }
+
+    /// Moves the cursor up over the lines drawn last and clears them.
+    fn erase(&self) -> String {
+        "\x1b[1A\x1b[2K".repeat(self.drawn)
+    }
+
+}
+
+fn download_line(name: &str, snapshot: &ProgressSnapshot) -> String {
+    let name = truncate(name, NAME_WIDTH);
+    let speed = format!("{}/s", format_bytes(snapshot.bytes_per_second as usize));
+    match snapshot.total {
+        Some(total) => {
+            let eta = snapshot.eta.map(format_duration).unwrap_or_else(|| "--:--".to_string());
+            format!("{:<width$} [{}] {:>3}% {:>10} / {:<10} {:>12}  ETA {}",
+                name, bar(snapshot.downloaded, total, BAR_WIDTH), percent(snapshot.downloaded, total),
+                format_bytes(snapshot.downloaded), format_bytes(total), speed, eta, width = NAME_WIDTH)
+        },
+        None => format!("{:<width$} {:>10} {:>12}", name, format_bytes(snapshot.downloaded), speed, width = NAME_WIDTH),
+    }
+}
+
+/// A small bar for every connection, showing how far its segment is.
+fn segments_line(snapshot: &ProgressSnapshot) -> String {
+    let mut line = format!("  {} connection{}", snapshot.segments.len(), if snapshot.segments.len() == 1 { "" } else { "s" });
+    for segment in &snapshot.segments {
+        let length = segment.range.end - segment.range.start + 1;
+        line.push_str(&format!(" [{}]", bar(segment.downloaded, length, SEGMENT_BAR_WIDTH)));
+    }
+    line
+}
+
+fn bar(done: usize, total: usize, width: usize) -> String {
+    let filled = (done * width).checked_div(total).map_or(width, |filled| usize::min(width, filled));
+    format!("{}{}", "#".repeat(filled), "-".repeat(width - filled))
+}
+
+fn percent(done: usize, total: usize) -> usize {
+    (done * 100).checked_div(total).map_or(100, |percent| usize::min(100, percent))
+}
+
+fn truncate(name: &str, width: usize) -> String {
+    if name.chars().count() <= width {
+        return name.to_string();
+    }
+    let kept: String = name.chars().take(width - 1).collect();
+    format!("{}~", kept)
+}
+
+fn format_bytes(bytes: usize) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} B", bytes)
+    }else {
+        format!("{:.1} {}", value, units[unit])
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 3600 {
+        format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    }else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{bar, format_bytes, format_duration, truncate};
+
+    #[test]
+    fn formats_sizes_durations_and_bars() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024), "3.0 MiB");
+        assert_eq!(format_duration(Duration::from_secs(65)), "1:05");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1:02:03");
+        assert_eq!(bar(1, 4, 8), "##------");
+        assert_eq!(truncate("a_very_long_file_name.iso", 10), "a_very_lo~");
+    }
+
+}
@@ -0,0 +1,29 @@
+use http_client::client::{auth::Credentials, proxy::{Proxies, Proxy}};
+use super::exit::Failure;
+
+/// The proxies from the environment, with `proxy` in place of them and
+/// `noproxy` in place of `no_proxy` when given. `None` when no request
+/// goes through a proxy.
+pub fn proxies(proxy: Option<&str>, proxy_user: Option<&str>, noproxy: Option<&str>) -> Result<Option<Proxies>, Failure> {
+    let mut proxies = Proxies::from_env();
+    if let Some(proxy) = proxy {
+        let proxy = Proxy::new(proxy)?;
+        proxies = proxies.set_http(proxy.clone()).set_https(proxy);
+    }
+    if let Some(noproxy) = noproxy {
+        proxies = proxies.set_no_proxy(noproxy);
+    }
+
+    if let Some(user) = proxy_user {
+        let (username, password) = user.split_once(':').unwrap_or((user, ""));
+        let credentials = Credentials::new(username, password);
+        if let Some(http) = proxies.get_http().cloned() {
+            proxies = proxies.set_http(http.set_credentials(credentials.clone()));
+        }
+        if let Some(https) = proxies.get_https().cloned() {
+            proxies = proxies.set_https(https.set_credentials(credentials));
+        }
+    }
+
+    Ok((proxies.get_http().is_some() || proxies.get_https().is_some()).then_some(proxies))
+}
@@ -0,0 +1,259 @@
+use std::path::Path;
+
+use http_client::client::{auth::Credentials, CookieJar};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use http_client::Error;
+use http_client::client::{client::Client, method::Method, request::Request, response::Response};
+use http_client::download::filename::{self, FilenameSource};
+use super::{args::RequestArgs, cookies, exit::Failure, netrc, proxy, resolve, tls};
+
+/// Sends the request described by `args` and writes the response.
+pub async fn run(args: &RequestArgs) -> Result<(), Failure> {
+    // Like curl, any cookie option turns cookie handling on.
+    let jar = if args.cookie.is_empty() && args.cookie_jar.is_none() {
+        None
+    }else {
+        let files: Vec<&Path> = args.cookie.iter().filter(|value| !value.contains('=')).map(Path::new).collect();
+        Some(cookies::open(&files).await?)
+    };
+
+    let result = match args.max_time {
+        Some(max_time) => tokio::time::timeout(max_time, send(args, jar.clone())).await
+            .unwrap_or_else(|_| Err(Error::Timeout.into())),
+        None => send(args, jar.clone()).await,
+    };
+
+    if let (Some(jar), Some(file)) = (&jar, &args.cookie_jar) {
+        cookies::save(jar, file).await?;
+    }
+    result
+}
+
+async fn send(args: &RequestArgs, jar: Option<CookieJar>) -> Result<(), Failure> {
+    let mut client = Client::new();
+    if let Some(timeout) = args.connect_timeout {
+        client = client.set_connect_timeout(timeout);
+    }
+    if let Some(jar) = jar {
+        client = client.set_cookie_jar(jar);
+    }
+    if let Some(netrc) = netrc::open(args.netrc, args.netrc_file.as_deref()).await? {
+        client = client.set_netrc(netrc);
+    }
+    client = client.set_resolver(resolve::resolver(&args.resolve, args.ipv4, args.ipv6)?);
+    if let Some(proxies) = proxy::proxies(args.proxy.as_deref(), args.proxy_user.as_deref(), args.noproxy.as_deref())? {
+        client = client.set_proxies(proxies);
+    }
+    client = client.set_tls_config(tls::config(
+        &args.cacert, args.ca_native, args.cert.as_deref(), args.key.as_deref(), args.pinnedpubkey.as_deref(), args.insecure
+    ).await?);
+
+    let mut connection = client.connect(&args.url).await?;
+    let mut request = build_request(args).await?;
+    let mut redirects = 0;
+
+    loop {
+        if args.verbose {
+            let head = connection.request_head(&request);
+            for line in head.lines().filter(|line| !line.is_empty()) {
+                eprintln!("> {}", line);
+            }
+            eprintln!(">");
+        }
+
+        let (response, mut stream) = connection.open(request.clone()).await?;
+
+        if args.verbose {
+            if let Some(address) = response.remote_addr {
+                eprintln!("* Connected to {}", address);
+            }
+            if let Some(tls) = &response.tls {
+                eprintln!("* {} with {}, ALPN {}", tls.version, tls.cipher_suite, tls.alpn_protocol.as_deref().unwrap_or("none"));
+                if let Some(pin) = tls.public_key_pin() {
+                    eprintln!("* Server public key {}", pin);
+                }
+            }
+            for line in response_head(&response).lines() {
+                eprintln!("< {}", line);
+            }
+        }
+
+        let location = response.header("Location").filter(|_| is_redirect(response.status_code));
+        if let (true, Some(location)) = (args.location, location) {
+            if redirects == args.max_redirs {
+                return Err(Error::TooManyRedirects.into());
+            }
+            redirects += 1;
+
+            let url = connection.parsed_url.resolve(location);
+            let previous = connection.parsed_url.clone();
+            connection.handle_redirect(&url).await?;
+            request = redirected(request, response.status_code);
+            if !previous.same_origin(&connection.parsed_url) {
+                request = request.strip_credentials();
+            }
+            continue;
+        }
+
+        if args.fail && response.status_code >= 400 {
+            return Err(Failure::http(response.status_code));
+        }
+
+        let mut output = open_output(args, &response, &connection.parsed_url.path).await?;
+        if args.include || args.head {
+            write(&mut output, response_head(&response).as_bytes()).await?;
+        }
+
+        let mut body = response.body_reader(&mut stream);
+        let mut chunk = vec![0u8; 64 * 1024];
+        // A response to HEAD has no body, whatever its headers announce.
+        while !matches!(request.get_method(), Method::HEAD) {
+            let read = body.read(&mut chunk).await?;
+            if read == 0 {
+                break;
+            }
+            write(&mut output, &chunk[..read]).await?;
+        }
+        output.flush().await.map_err(|e| Failure::write(e.into()))?;
+        return Ok(());
+    }
+}
+
+async fn build_request(args: &RequestArgs) -> Result<Request, Failure> {
+    let mut request = Request::new();
+    let has_header = |name: &str| args.headers.iter()
+        .any(|header| header.split(':').next().unwrap_or_default().trim().eq_ignore_ascii_case(name));
+
+    let mut data = vec![];
+    for value in &args.data {
+        data.push(read_argument(value, true).await?);
+    }
+    for value in &args.data_binary {
+        data.push(read_argument(value, false).await?);
+    }
+    if !data.is_empty() {
+        request = request.set_body(data.join(&b'&'));
+        if !has_header("Content-Type") {
+            request = request.form_data();
+        }
+    }
+
+    if !args.json.is_empty() {
+        let mut json = vec![];
+        for value in &args.json {
+            json.extend(read_argument(value, false).await?);
+        }
+        request = request.set_body(json)
+            .add_header("Content-Type", "application/json")
+            .add_header("Accept", "application/json");
+    }
+
+    if !args.form.is_empty() {
+        request = request.multipart();
+        for field in &args.form {
+            let (name, value) = field.split_once('=')
+                .ok_or_else(|| Failure::usage(format!("`{}` is not a name=value field", field)))?;
+            request = match value.strip_prefix('@') {
+                Some(path) => {
+                    let bytes = tokio::fs::read(path).await.map_err(|e| Failure::read(e.into()))?;
+                    let file_name = Path::new(path).file_name().unwrap_or_default().to_string_lossy().to_string();
+                    request.add_file(name, &file_name, bytes)
+                },
+                None => request.add_data(name, value),
+            };
+        }
+    }
+
+    if let Some(user) = &args.user {
+        let (username, password) = user.split_once(':').unwrap_or((user, ""));
+        request = request.set_credentials(Credentials::new(username, password));
+    }
+    if let Some(token) = &args.oauth2_bearer {
+        request = request.bearer_auth(token);
+    }
+
+    let cookies: Vec<&str> = args.cookie.iter().filter(|value| value.contains('=')).map(String::as_str).collect();
+    if !cookies.is_empty() {
+        request = request.add_header("Cookie", cookies.join("; "));
+    }
+
+    for header in &args.headers {
+        let (name, value) = header.split_once(':')
+            .ok_or_else(|| Failure::usage(format!("`{}` is not a \"Name: value\" header", header)))?;
+        request = request.remove_header(name.trim()).add_header(name.trim(), value.trim());
+    }
+
+    let sends_data = request.get_body().is_some();
+    let method = match &args.method {
+        Some(method) => method.clone(),
+        None if args.head => Method::HEAD,
+        None if sends_data => Method::POST,
+        None => Method::GET,
+    };
+    Ok(request.set_method(method))
+}
+
+/// A value given on the command line, or the content of the file it names
+/// with "@file". Line breaks are dropped from files if `strip` is set.
+async fn read_argument(value: &str, strip: bool) -> Result<Vec<u8>, Failure> {
+    match value.strip_prefix('@') {
+        Some(path) => {
+            let mut bytes = tokio::fs::read(path).await.map_err(|e| Failure::read(e.into()))?;
+            if strip {
+                bytes.retain(|byte| *byte != b'\r' && *byte != b'\n');
+            }
+            Ok(bytes)
+        },
+        None => Ok(value.as_bytes().to_vec()),
+    }
+}
+
+fn is_redirect(status_code: usize) -> bool {
+    matches!(status_code, 301 | 302 | 303 | 307 | 308)
+}
+
+/// The request to send to the new location. Like browsers, a POST turns
+/// into a GET after 301 and 302, and everything but HEAD does after 303.
+fn redirected(request: Request, status_code: usize) -> Request {
+    let to_get = match request.get_method() {
+        Method::HEAD | Method::GET => false,
+        Method::POST => (301..=303).contains(&status_code),
+        _ => status_code == 303,
+    };
+
+    if to_get {
+        request.set_method(Method::GET).clear_body().remove_header("Content-Type")
+    }else {
+        request
+    }
+}
+
+fn response_head(response: &Response) -> String {
+    let mut head = format!("{} {} {}\r\n", response.version, response.status_code, response.status_name);
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value.trim()));
+    }
+    head.push_str("\r\n");
+    head
+}
+
+async fn open_output(args: &RequestArgs, response: &Response, url_path: &str) -> Result<Box<dyn AsyncWrite + Unpin + Send>, Failure> {
+    let path = match &args.output {
+        Some(path) if path.is_dir() => path.join(filename::derive(
+            &[FilenameSource::ContentDisposition, FilenameSource::Url],
+            response.header("Content-Disposition"),
+            url_path,
+            response.header("Content-Type")
+        )),
+        Some(path) => path.clone(),
+        None => return Ok(Box::new(tokio::io::stdout())),
+    };
+
+    let file = tokio::fs::File::create(path).await.map_err(|e| Failure::write(e.into()))?;
+    Ok(Box::new(file))
+}
+
+async fn write(output: &mut Box<dyn AsyncWrite + Unpin + Send>, bytes: &[u8]) -> Result<(), Failure> {
+    output.write_all(bytes).await.map_err(|e| Failure::write(e.into()))
+}
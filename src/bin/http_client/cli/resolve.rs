@@ -0,0 +1,33 @@
+use std::net::IpAddr;
+
+use http_client::client::resolve::{IpPreference, Resolver};
+use super::exit::Failure;
+
+/// The resolver the flags describe: `resolve` holds curl style
+/// `host:port:address[,address]` overrides.
+pub fn resolver(resolve: &[String], ipv4: bool, ipv6: bool) -> Result<Resolver, Failure> {
+    let preference = match (ipv4, ipv6) {
+        (true, _) => IpPreference::Ipv4Only,
+        (_, true) => IpPreference::Ipv6Only,
+        _ => IpPreference::Any,
+    };
+    let mut resolver = Resolver::new().set_ip_preference(preference);
+
+    for entry in resolve {
+        let invalid = || Failure::usage(format!("--resolve `{}` is not HOST:PORT:ADDR[,ADDR]...", entry));
+        let mut parts = entry.splitn(3, ':');
+        let (Some(host), Some(port), Some(addresses)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(invalid());
+        };
+        let port = match port {
+            "*" => None,
+            port => Some(port.parse::<usize>().map_err(|_| invalid())?),
+        };
+        let addresses = addresses.split(',')
+            .map(|address| address.trim().trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>())
+            .collect::<Result<Vec<IpAddr>, _>>()
+            .map_err(|_| invalid())?;
+        resolver = resolver.add_override(host, port, addresses);
+    }
+    Ok(resolver)
+}
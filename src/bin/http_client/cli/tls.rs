@@ -0,0 +1,34 @@
+use std::path::{Path, PathBuf};
+
+use http_client::client::tls::{Pin, TlsConfig};
+use super::exit::Failure;
+
+/// The TLS configuration the flags describe: `cacert` replaces the bundled
+/// roots, `cert` holds the key too unless `key` is given and `pins` are
+/// separated by ';'.
+pub async fn config(cacert: &[PathBuf], ca_native: bool, cert: Option<&Path>, key: Option<&Path>, pins: Option<&str>, insecure: bool) -> Result<TlsConfig, Failure> {
+    let mut config = TlsConfig::new()
+        .set_webpki_roots(cacert.is_empty())
+        .set_accept_invalid_certs(insecure);
+
+    for file in cacert {
+        config = config.add_ca_file(file).await.map_err(read_failure)?;
+    }
+    if ca_native {
+        config = config.add_native_roots().await?;
+    }
+    if let Some(cert) = cert {
+        config = config.load_client_certificate(cert, key.unwrap_or(cert)).await.map_err(read_failure)?;
+    }
+    for pin in pins.iter().flat_map(|pins| pins.split(';')) {
+        config = config.add_pin(Pin::parse(pin)?);
+    }
+    Ok(config)
+}
+
+fn read_failure(error: http_client::Error) -> Failure {
+    match error {
+        e @ http_client::Error::IoError(_) => Failure::read(e),
+        e => e.into(),
+    }
+}
@@ -0,0 +1,28 @@
+use std::process::ExitCode;
+
+use clap::Parser;
+
+use cli::args::{Cli, Command};
+
+mod cli;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let (result, silent) = match (&cli.command, &cli.request) {
+        (Some(Command::Download(args)), _) => (cli::download::run(args).await, args.silent),
+        (None, Some(args)) => (cli::request::run(args).await, args.silent),
+        (None, None) => unreachable!("clap requires a URL without a subcommand"),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            if !silent {
+                eprintln!("http_client: ({}) {}", failure.code, failure.message);
+            }
+            ExitCode::from(failure.code)
+        },
+    }
+}
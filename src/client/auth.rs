@@ -0,0 +1,225 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+#[cfg(feature = "digest-auth")]
+use rand::Rng;
+
+/// A user name and password, answered to whatever scheme the server asks
+/// for in its `WWW-Authenticate` challenge.
+#[derive(Clone, PartialEq)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String
+}
+
+impl std::fmt::Debug for Credentials {
+    // Keeps passwords out of logs.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials").field("username", &self.username).finish_non_exhaustive()
+    }
+}
+
+impl Credentials {
+
+    pub fn new(username: &str, password: &str) -> Credentials {
+        Credentials { username: username.to_string(), password: password.to_string() }
+    }
+
+    /// The `Authorization` value for the Basic scheme.
+    pub fn basic(&self) -> String {
+        format!("Basic {}", STANDARD.encode(format!("{}:{}", self.username, self.password)))
+    }
+
+}
+
+/// One challenge of a `WWW-Authenticate` header: the scheme and its
+/// parameters, with names lowercased and quotes removed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Challenge {
+    pub scheme: String,
+    pub params: Vec<(String, String)>
+}
+
+impl Challenge {
+
+    /// Parses a `WWW-Authenticate` value, which may hold several
+    /// challenges separated by commas like their parameters.
+    pub fn parse_all(value: &str) -> Vec<Challenge> {
+        let mut challenges: Vec<Challenge> = vec![];
+        let mut rest = value.trim_start();
+
+        while !rest.is_empty() {
+            let token_end = rest.find([' ', ',', '=']).unwrap_or(rest.len());
+            let (token, after) = rest.split_at(token_end);
+            let after_spaces = after.trim_start();
+
+            if let Some(value) = after_spaces.strip_prefix('=').filter(|_| !token.is_empty()) {
+                // A parameter of the current challenge, or token68 padding.
+                let (value, remaining) = parse_param_value(value.trim_start());
+                if let Some(challenge) = challenges.last_mut() {
+                    challenge.params.push((token.to_lowercase(), value));
+                }
+                rest = remaining;
+            }else if !token.is_empty() {
+                challenges.push(Challenge { scheme: token.to_lowercase(), params: vec![] });
+                rest = after;
+            }else {
+                rest = &rest[1..];
+            }
+            rest = rest.trim_start_matches([' ', ',']);
+        }
+        challenges
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+}
+
+/// A quoted string or a token, and what follows it.
+fn parse_param_value(input: &str) -> (String, &str) {
+    match input.strip_prefix('"') {
+        Some(quoted) => {
+            let mut value = String::new();
+            let mut chars = quoted.char_indices();
+            while let Some((index, c)) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next().map(|(_, escaped)| escaped)),
+                    '"' => return (value, &quoted[index + 1..]),
+                    c => value.push(c),
+                }
+            }
+            (value, "")
+        },
+        None => {
+            let end = input.find([',', ' ']).unwrap_or(input.len());
+            (input[..end].to_string(), &input[end..])
+        },
+    }
+}
+
+/// The `Authorization` value answering `challenges` for a request of
+/// `method` to `uri`, or `None` when no offered scheme is supported.
+/// Digest is preferred over Basic, which sends the password in the clear.
+pub fn authorization(challenges: &[Challenge], credentials: &Credentials, method: &str, uri: &str) -> Option<String> {
+    #[cfg(feature = "digest-auth")]
+    for challenge in challenges.iter().filter(|challenge| challenge.scheme == "digest") {
+        if let Some(authorization) = digest(challenge, credentials, method, uri, &cnonce()) {
+            return Some(authorization);
+        }
+    }
+    #[cfg(not(feature = "digest-auth"))]
+    let _ = (method, uri);
+
+    challenges.iter()
+        .any(|challenge| challenge.scheme == "basic")
+        .then(|| credentials.basic())
+}
+
+#[cfg(feature = "digest-auth")]
+fn cnonce() -> String {
+    let bytes: [u8; 16] = rand::thread_rng().gen();
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The Digest response of RFC 7616 for MD5 and SHA-256, plain or `-sess`,
+/// with `qop=auth` or the RFC 2069 form without qop.
+#[cfg(feature = "digest-auth")]
+fn digest(challenge: &Challenge, credentials: &Credentials, method: &str, uri: &str, cnonce: &str) -> Option<String> {
+    use md5::Md5;
+    use sha2::{Digest, Sha256};
+
+    let realm = challenge.param("realm").unwrap_or_default();
+    let nonce = challenge.param("nonce")?;
+    let algorithm = challenge.param("algorithm").unwrap_or("MD5");
+    let hash: fn(&str) -> String = match algorithm.to_ascii_uppercase().trim_end_matches("-SESS") {
+        "MD5" => |data| hex(&Md5::digest(data.as_bytes())),
+        "SHA-256" => |data| hex(&Sha256::digest(data.as_bytes())),
+        _ => return None,
+    };
+    let qop = match challenge.param("qop") {
+        Some(offered) if offered.split(',').any(|qop| qop.trim() == "auth") => Some("auth"),
+        Some(_) => return None,
+        None => None,
+    };
+    let nc = "00000001";
+
+    let mut ha1 = hash(&format!("{}:{}:{}", credentials.username, realm, credentials.password));
+    if algorithm.to_ascii_uppercase().ends_with("-SESS") {
+        ha1 = hash(&format!("{}:{}:{}", ha1, nonce, cnonce));
+    }
+    let ha2 = hash(&format!("{}:{}", method, uri));
+    let response = match qop {
+        Some(qop) => hash(&format!("{}:{}:{}:{}:{}:{}", ha1, nonce, nc, cnonce, qop, ha2)),
+        None => hash(&format!("{}:{}:{}", ha1, nonce, ha2)),
+    };
+
+    let mut authorization = format!(
+        "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", algorithm={}, response=\"{}\"",
+        quote(&credentials.username), quote(realm), quote(nonce), quote(uri), algorithm, response
+    );
+    if let Some(qop) = qop {
+        authorization.push_str(&format!(", qop={}, nc={}, cnonce=\"{}\"", qop, nc, cnonce));
+    }
+    if let Some(opaque) = challenge.param("opaque") {
+        authorization.push_str(&format!(", opaque=\"{}\"", quote(opaque)));
+    }
+    Some(authorization)
+}
+
+#[cfg(feature = "digest-auth")]
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(feature = "digest-auth")]
+fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{authorization, Challenge, Credentials};
+
+    #[test]
+    fn parses_several_challenges() {
+        let challenges = Challenge::parse_all(
+            r#"Digest realm="api, v2", qop="auth,auth-int", nonce=abc, Basic realm="x", Bearer"#
+        );
+
+        assert_eq!(challenges.len(), 3);
+        assert_eq!(challenges[0].scheme, "digest");
+        assert_eq!(challenges[0].param("realm"), Some("api, v2"));
+        assert_eq!(challenges[0].param("qop"), Some("auth,auth-int"));
+        assert_eq!(challenges[0].param("nonce"), Some("abc"));
+        assert_eq!(challenges[1].param("realm"), Some("x"));
+        assert_eq!(challenges[2].scheme, "bearer");
+    }
+
+    #[test]
+    fn basic_when_only_basic_is_offered() {
+        let credentials = Credentials::new("Aladdin", "open sesame");
+        let challenges = Challenge::parse_all("Basic realm=\"WallyWorld\"");
+        assert_eq!(authorization(&challenges, &credentials, "GET", "/").as_deref(), Some("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="));
+        assert_eq!(authorization(&Challenge::parse_all("Bearer"), &credentials, "GET", "/"), None);
+    }
+
+    /// The examples of RFC 7616 section 3.9.1.
+    #[cfg(feature = "digest-auth")]
+    #[test]
+    fn digest_matches_the_rfc_examples() {
+        let credentials = Credentials::new("Mufasa", "Circle of Life");
+        let cnonce = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+        let challenge = |algorithm: &str| Challenge::parse_all(&format!(
+            r#"Digest realm="http-auth@example.org", qop="auth, auth-int", algorithm={}, nonce="7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v", opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#,
+            algorithm
+        )).remove(0);
+
+        let md5 = super::digest(&challenge("MD5"), &credentials, "GET", "/dir/index.html", cnonce).unwrap();
+        assert!(md5.contains("response=\"8ca523f5e9506fed4657c9700eebdbec\""), "{}", md5);
+        assert!(md5.contains("qop=auth, nc=00000001"));
+
+        let sha256 = super::digest(&challenge("SHA-256"), &credentials, "GET", "/dir/index.html", cnonce).unwrap();
+        assert!(sha256.contains("response=\"753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1\""), "{}", sha256);
+    }
+
+}
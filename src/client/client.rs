@@ -0,0 +1,143 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio_rustls::TlsConnector;
+
+use crate::app::error::Error;
+use super::{auth::Credentials, connection::Connection, cookie::CookieJar, netrc::Netrc, proxy::Proxies, resolve::Resolver, throttle::RateLimiter, tls::{SharedTls, TlsConfig, TlsStats}};
+
+/// Settings shared by every `Connection` made from it.
+///
+/// Cloning is cheap and clones share their state, e.g. a rate limiter set
+/// here caps all connections of the client together.
+#[derive(Clone, Debug, Default)]
+pub struct Client {
+    rate_limiter: Option<RateLimiter>,
+    connect_timeout: Option<Duration>,
+    cookie_jar: Option<CookieJar>,
+    netrc: Option<Netrc>,
+    proxies: Option<Proxies>,
+    tls_config: TlsConfig,
+    shared_tls: Arc<SharedTls>,
+    resolver: Resolver
+}
+
+impl Client {
+
+    pub fn new() -> Client {
+        Client::default()
+    }
+
+    pub async fn connect(&self, url: &str) -> Result<Connection, Error> {
+        Connection::with_client(url, self.clone()).await
+    }
+
+    /// Caps the combined throughput of all downloads and uploads, except
+    /// the uploads of requests that have their own limiter.
+    pub fn set_rate_limiter(mut self, rate_limiter: RateLimiter) -> Client {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    pub fn get_rate_limiter(&self) -> Option<&RateLimiter> {
+        self.rate_limiter.as_ref()
+    }
+
+    /// Limits how long connecting, including the TLS handshake, may take
+    /// before failing with `Error::Timeout`.
+    pub fn set_connect_timeout(mut self, timeout: Duration) -> Client {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn get_connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout
+    }
+
+    /// Stores the cookies servers set and sends them with later requests.
+    pub fn set_cookie_jar(mut self, cookie_jar: CookieJar) -> Client {
+        self.cookie_jar = Some(cookie_jar);
+        self
+    }
+
+    pub fn get_cookie_jar(&self) -> Option<&CookieJar> {
+        self.cookie_jar.as_ref()
+    }
+
+    /// Looks up credentials for requests that have none, neither set on the
+    /// request nor in the URL. Like curl and wget, those of a `machine` entry
+    /// for the host are sent as Basic auth up front, since many servers
+    /// answer 403 or 404 instead of a challenge. The `default` entry is only
+    /// used to answer a challenge, with Digest when the server asks for it.
+    pub fn set_netrc(mut self, netrc: Netrc) -> Client {
+        self.netrc = Some(netrc);
+        self
+    }
+
+    pub fn get_netrc(&self) -> Option<&Netrc> {
+        self.netrc.as_ref()
+    }
+
+    /// Sends requests through proxies, e.g. `Proxies::from_env()`.
+    pub fn set_proxies(mut self, proxies: Proxies) -> Client {
+        self.proxies = Some(proxies);
+        self
+    }
+
+    pub fn get_proxies(&self) -> Option<&Proxies> {
+        self.proxies.as_ref()
+    }
+
+    /// Resolves the hosts connected to, proxies included, e.g. with host
+    /// overrides or another DNS cache time.
+    pub fn set_resolver(mut self, resolver: Resolver) -> Client {
+        self.resolver = resolver;
+        self
+    }
+
+    pub fn get_resolver(&self) -> &Resolver {
+        &self.resolver
+    }
+
+    /// Trusted roots, client certificate and pins for `https` connections.
+    /// Starts a new session cache and new `tls_stats`.
+    pub fn set_tls_config(mut self, tls_config: TlsConfig) -> Client {
+        self.tls_config = tls_config;
+        self.shared_tls = Arc::default();
+        self
+    }
+
+    pub fn get_tls_config(&self) -> &TlsConfig {
+        &self.tls_config
+    }
+
+    /// The TLS handshakes of the connections made so far, and how many of
+    /// them were resumed.
+    pub fn tls_stats(&self) -> TlsStats {
+        self.shared_tls.stats()
+    }
+
+    /// The TLS setup every connection of the client shares, built on first
+    /// use.
+    pub(crate) fn tls_connector(&self) -> Result<TlsConnector, Error> {
+        self.shared_tls.connector(&self.tls_config)
+    }
+
+    pub(crate) fn tls_handshake_completed(&self) {
+        self.shared_tls.handshake_completed();
+    }
+
+    pub(crate) fn netrc_credentials(&self, host: &str) -> Option<Credentials> {
+        self.netrc.as_ref().and_then(|netrc| netrc.credentials(host))
+    }
+
+    pub(crate) fn netrc_machine_credentials(&self, host: &str) -> Option<Credentials> {
+        self.netrc.as_ref().and_then(|netrc| netrc.machine_credentials(host))
+    }
+
+    pub(crate) async fn throttle(&self, bytes: usize) {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire(bytes).await;
+        }
+    }
+
+}
@@ -1,51 +1,51 @@
-use std::{path::PathBuf, sync::{mpsc::{Sender, Receiver}, Arc}};
+use std::future::Future;
 
-use rand::{thread_rng, Rng};
-use tokio::{net::TcpStream, io::{AsyncWriteExt, AsyncSeekExt}, fs::OpenOptions, sync::Semaphore};
-use tokio_rustls::rustls::{ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName};
+use tokio::{net::TcpStream, io::AsyncWriteExt};
+use tokio_rustls::rustls::ServerName;
 use tokio_rustls::TlsConnector;
-use tokio_rustls::webpki::DnsNameRef;
+use tokio_util::sync::CancellationToken;
 
 use crate::app::error::Error;
-use super::{url::ParsedUrl, response::Response, method::Method, request::Request};
-
-
-static SEM: Semaphore = Semaphore::const_new(0);
+use super::{auth::{self, Challenge}, client::Client, proxy::{Proxy, ProxyKind}, url::ParsedUrl, response::Response, request::Request, method::Method};
+pub use super::stream::HttpStream;
 
+/// A server to send requests to, given by a URL. Every request opens a new
+/// connection to it, over TLS for `https` URLs.
 #[derive(Clone)]
 pub struct Connection {
     pub parsed_url: ParsedUrl,
     pub config: TlsConnector,
-    pub dns_name: ServerName
+    pub dns_name: ServerName,
+    pub client: Client
 }
 
+/// Bytes of a request body written at once.
+const UPLOAD_CHUNK_SIZE: usize = 16 * 1024;
+
 impl Connection {
     
     pub async fn new(url: &str) -> Result<Connection, Error> {
+        Self::with_client(url, Client::new()).await
+    }
+
+    pub async fn with_client(url: &str, client: Client) -> Result<Connection, Error> {
         let parsed_url = ParsedUrl::from(url)?;
-        let mut root_cert_store = RootCertStore::empty();
-        root_cert_store.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(
-            |ta| {
-                OwnedTrustAnchor::from_subject_spki_name_constraints(
-                    ta.subject,
-                    ta.spki,
-                    ta.name_constraints,
-                )
-            },
-        ));
-        let config = ClientConfig::builder()
-            .with_safe_defaults()
-            .with_root_certificates(root_cert_store)
-            .with_no_client_auth();
-        let config = TlsConnector::from(Arc::new(config));
-        let dns_name = ServerName::try_from(parsed_url.host.as_str()).unwrap();
+        let config = client.tls_connector()?;
+        let dns_name = ServerName::try_from(parsed_url.host.as_str()).map_err(|_| Error::UrlParsingError)?;
         Ok(
-            Connection { parsed_url, config, dns_name }
+            Connection { parsed_url, config, dns_name, client }
         )
     }
 
+    /// Points the connection at `new_url`. The userinfo of the old URL is
+    /// kept for a new URL of the same origin that has none, and dropped for
+    /// any other.
     pub async fn handle_redirect(&mut self, new_url: &str) -> Result<(), Error> {
-        let new_connection = Self::new(new_url).await?;
+        let mut new_connection = Self::with_client(new_url, self.client.clone()).await?;
+        if new_connection.parsed_url.username.is_none() && self.parsed_url.same_origin(&new_connection.parsed_url) {
+            new_connection.parsed_url.username = self.parsed_url.username.take();
+            new_connection.parsed_url.password = self.parsed_url.password.take();
+        }
         self.parsed_url = new_connection.parsed_url;
         self.config = new_connection.config;
         self.dns_name = new_connection.dns_name;
@@ -53,152 +53,272 @@ impl Connection {
     }
 
     pub async fn request(&self, request: Request) -> Result<Response, Error> {
-        let mut stream = TcpStream::connect(
-            format!("{}:{}", self.parsed_url.host, self.parsed_url.port)
-        ).await?;
-        let mut stream = self.config.connect(self.dns_name.clone(), stream).await?;
+        let cancellation = request.get_cancellation().cloned();
+        let head = matches!(request.get_method(), Method::HEAD);
+        cancellable(cancellation.as_ref(), async {
+            let (mut response, mut stream) = self.send(request).await?;
+            // A response to HEAD has no body, whatever its headers announce.
+            if !head {
+                response.read_body(&mut stream).await?;
+            }
+            Ok(response)
+        }).await
+    }
 
-        let path = if request.get_query_strings().is_empty() {
-            self.parsed_url.path.to_string()
-        }else {
-            format!("{}?{}",self.parsed_url.path, request.get_query_strings())
+    /// Sends `request` and returns once the response head is read. The body
+    /// is left in the returned stream for the caller to consume.
+    pub async fn open(&self, request: Request) -> Result<(Response, HttpStream), Error> {
+        let cancellation = request.get_cancellation().cloned();
+        cancellable(cancellation.as_ref(), self.send(request)).await
+    }
+
+    /// Sends `request`, and once more with an `Authorization` header when
+    /// the server challenges it with `401` and there are credentials for it.
+    /// Credentials of a netrc `machine` entry go along as Basic right away.
+    async fn send(&self, request: Request) -> Result<(Response, HttpStream), Error> {
+        let authorized = request.get_headers().keys().any(|name| name.eq_ignore_ascii_case("Authorization"));
+        let given = request.get_credentials().cloned().or_else(|| self.parsed_url.credentials());
+        let netrc = self.client.netrc_machine_credentials(&self.parsed_url.host).filter(|_| !authorized && given.is_none());
+
+        let (response, stream) = match &netrc {
+            Some(credentials) => self.exchange(&request.clone().add_header("Authorization", credentials.basic())).await?,
+            None => self.exchange(&request).await?,
         };
 
-        stream.write_all(format!("{} {} HTTP/1.1\r\n", request.get_method(), path).as_bytes()).await?;
-        stream.write_all(format!("HOST: {}\r\n", self.parsed_url.host).as_bytes()).await?;
-        
-        for header in request.get_headers() {
-            stream.write_all(
-                format!("{}: {}\r\n", header.0, header.1).as_bytes()
-            ).await?;
+        let credentials = given
+            .or(netrc.clone())
+            .or_else(|| self.client.netrc_credentials(&self.parsed_url.host));
+        let Some(credentials) = credentials.filter(|_| response.status_code == 401 && !authorized) else {
+            return Ok((response, stream));
+        };
+
+        let challenges: Vec<Challenge> = response.header_all("WWW-Authenticate").into_iter()
+            .flat_map(Challenge::parse_all)
+            .collect();
+        let method = request.get_method().to_string();
+        match auth::authorization(&challenges, &credentials, &method, &self.request_target(&request)) {
+            // Basic with the same credentials was already refused.
+            Some(authorization) if netrc.is_some() && authorization.starts_with("Basic ") => Ok((response, stream)),
+            Some(authorization) => self.exchange(&request.add_header("Authorization", authorization)).await,
+            None => Ok((response, stream)),
         }
+    }
 
-        stream.write_all(
-            format!("Content-Length: {}\r\n", request.get_content_length()).as_bytes()
-        ).await?;
+    async fn exchange(&self, request: &Request) -> Result<(Response, HttpStream), Error> {
+        let mut stream = match self.client.get_connect_timeout() {
+            Some(timeout) => tokio::time::timeout(timeout, self.connect()).await.map_err(|_| Error::Timeout)??,
+            None => self.connect().await?,
+        };
 
-        if let Some(range) = request.get_range() {
-            stream.write_all(
-                format!("Range: bytes={}-{}\r\n", range.start, range.end).as_bytes()
-            ).await?;
-        }
-        
-        stream.write_all(b"Connection: Close\r\n").await?;
-        stream.write_all(b"\r\n").await?;
+        stream.write_all(self.request_head(request).as_bytes()).await?;
 
         if let Some(body) = request.get_body() {
-            stream.write_all(body.as_slice()).await?;
+            for chunk in body.chunks(UPLOAD_CHUNK_SIZE) {
+                match request.get_rate_limiter() {
+                    Some(rate_limiter) => rate_limiter.acquire(chunk.len()).await,
+                    None => self.client.throttle(chunk.len()).await,
+                }
+                stream.write_all(chunk).await?;
+            }
         }
 
-        stream.write_all(b"\r\n\r\n").await?;
+        let mut response = Response::read_head(&mut stream).await?;
+        response.remote_addr = stream.remote_addr();
+        response.tls = stream.tls_info();
+        if let Some(jar) = self.client.get_cookie_jar() {
+            jar.store(&self.parsed_url, &response);
+        }
+        Ok((response, stream))
+    }
+
+    async fn connect(&self) -> Result<HttpStream, Error> {
+        let resolver = self.client.get_resolver();
+        let stream = match self.proxy() {
+            Some(proxy) => proxy.open(&self.parsed_url, resolver).await?,
+            None => TcpStream::connect(&resolver.resolve(&self.parsed_url.host, self.parsed_url.port).await?[..]).await?,
+        };
 
-        Ok(Response::new(&mut stream).await?)
+        if self.parsed_url.scheme == "https" {
+            // Failed certificate and pin checks come back as I/O errors.
+            let stream = self.config.connect(self.dns_name.clone(), stream).await
+                .map_err(|e| Error::TlsError(e.to_string()))?;
+            self.client.tls_handshake_completed();
+            Ok(HttpStream::Tls(Box::new(stream)))
+        }else {
+            Ok(HttpStream::Plain(stream))
+        }
     }
 
-    pub async fn download(&mut self, path: &PathBuf) -> Result<(), Error> {
-        let head_request = Request::new().set_method(Method::HEAD);
-        let mut head_response = self.request(head_request.clone()).await?;
+    /// The request line and headers sent for `request`, ending with the
+    /// empty line that separates them from the body.
+    pub fn request_head(&self, request: &Request) -> String {
+        let mut head = format!("{} {} HTTP/1.1\r\n", request.get_method(), self.request_target(request));
+        head.push_str(&format!("HOST: {}\r\n", self.parsed_url.host));
 
-        if head_response.status_code == 302 {
-            match head_response.headers.get("Location") {
-                Some(v) => {
-                    self.handle_redirect(v.trim()).await?;
-                    head_response = self.request(head_request).await?;
-                }
-                None => {
-                    panic!("There is no `Location` key in headers for follow.")
-                }
+        let mut cookies = vec![];
+        for header in request.get_headers() {
+            // Cookies of the request and the jar go out in a single header.
+            if header.0.eq_ignore_ascii_case("Cookie") {
+                cookies.push(header.1.to_string());
+                continue;
             }
+            head.push_str(&format!("{}: {}\r\n", header.0, header.1));
         }
 
-        let mut file_path = PathBuf::from(path.to_str().unwrap());
-        let mut file_name = String::new();
-
-        if file_path.is_dir() {
-            if let Some(fname) = &self.parsed_url.file {
-                file_name.push_str(fname);
-            }else {
-                if let Some(content_disposition) = head_response.headers.get("Content-Disposition") {
-                    let fname = content_disposition.split("=").last().unwrap();
-                    file_name.push_str(fname.trim_matches('"'));
-                }else {
-                    let fname: String = thread_rng()
-                        .sample_iter(&rand::distributions::Alphanumeric)
-                        .take(15)
-                        .map(char::from)
-                        .collect();
-                    file_name.push_str(&fname);
-                }
-            }
+        if let Some(jar_cookies) = self.client.get_cookie_jar().and_then(|jar| jar.header(&self.parsed_url)) {
+            cookies.push(jar_cookies);
+        }
+        if !cookies.is_empty() {
+            head.push_str(&format!("Cookie: {}\r\n", cookies.join("; ")));
+        }
 
-            file_path = file_path.join(file_name);
+        if let Some(authorization) = self.forward_proxy().and_then(Proxy::authorization) {
+            head.push_str(&format!("Proxy-Authorization: {}\r\n", authorization));
         }
 
-        let mut file = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .open(&file_path)
-            .await?;
+        head.push_str(&format!("Content-Length: {}\r\n", request.get_content_length()));
 
-        let content_length: usize = match head_response.headers.get("Content-Length") {
-            Some(v) => v.trim().parse().unwrap(),
-            None => 0,
+        if let Some(range) = request.get_range() {
+            head.push_str(&format!("Range: bytes={}-{}\r\n", range.start, range.end));
+        }
+
+        head.push_str("Connection: Close\r\n");
+        head.push_str("\r\n");
+        head
+    }
+
+    /// The path and query the request line asks for, after the origin when
+    /// a proxy forwards the request.
+    fn request_target(&self, request: &Request) -> String {
+        let path = if request.get_query_strings().is_empty() {
+            self.parsed_url.path.to_string()
+        }else {
+            format!("{}?{}",self.parsed_url.path, request.get_query_strings())
         };
-        let connection_count = 5;
-        SEM.add_permits(connection_count);
-        let each_segment = 500_000;
-
-        if content_length != 0 && content_length > each_segment {
-            let (sender, receiver): (Sender<Response>, Receiver<Response>) = std::sync::mpsc::channel();
-            let mut steps_left = content_length / each_segment;
-            let mut range = 0..each_segment;
-            let arc_self = Arc::new(self.clone());
-
-            tokio::spawn(async move {                
-                while let Ok(permit) = SEM.acquire().await {
-                    let current_range = range.clone();
-                    let _sender = sender.clone();
-                    let _self = Arc::clone(&arc_self);
-
-                    tokio::spawn(async move {
-                        println!("spawn");
-                        let _permit = permit;
-                        let request = Request::new().set_range(current_range.clone());
-                        let mut response = _self.request(request).await.unwrap();
-                        response.range = Some(current_range);
-
-                        _sender.send(response).unwrap();
-                    });
-
-                    range = if range.end + each_segment > content_length {
-                        range.end + 1..content_length
-                    }else {
-                        range.end + 1..range.end + each_segment
-                    };
-
-                    if steps_left == 0 {
-                        break;
+
+        match self.forward_proxy() {
+            Some(_) => format!("{}{}", self.parsed_url.origin(), path),
+            None => path,
+        }
+    }
+
+    fn proxy(&self) -> Option<&Proxy> {
+        self.client.get_proxies().and_then(|proxies| proxies.proxy_for(&self.parsed_url))
+    }
+
+    /// The proxy plain `http` requests are sent to as they are, rather than
+    /// through a tunnel.
+    fn forward_proxy(&self) -> Option<&Proxy> {
+        self.proxy().filter(|proxy| proxy.kind() == ProxyKind::Http && self.parsed_url.scheme != "https")
+    }
+
+}
+
+/// Runs `future` until it completes or `token` is cancelled, in which case it
+/// is dropped along with the sockets it holds.
+pub(crate) async fn cancellable<T, F>(token: Option<&CancellationToken>, future: F) -> Result<T, Error>
+        where F: Future<Output = Result<T, Error>> {
+    match token {
+        Some(token) => tokio::select! {
+            biased;
+            _ = token.cancelled() => Err(Error::Cancelled),
+            result = future => result,
+        },
+        None => future.await,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener};
+
+    use crate::app::error::Error;
+    use crate::client::{client::Client, netrc::Netrc, request::Request, throttle::RateLimiter};
+    use super::Connection;
+
+    #[tokio::test]
+    async fn netrc_credentials_are_sent_up_front() {
+        // Like many mirrors, the server refuses without asking for credentials.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut head = vec![];
+                while !head.ends_with(b"\r\n\r\n") {
+                    match socket.read_u8().await {
+                        Ok(byte) => head.push(byte),
+                        Err(_) => break,
                     }
-                    steps_left -= 1;
                 }
-            });
+                let authorized = String::from_utf8_lossy(&head).contains("Authorization: Basic YWxpY2U6c2VjcmV0\r\n");
+                let status = if authorized { "200 OK" } else { "403 Forbidden" };
+                let _ = socket.write_all(format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).as_bytes()).await;
+            }
+        });
 
-        while let Ok(response) = receiver.recv() {
-            println!("received");
-            file.seek(std::io::SeekFrom::Start(response.range.unwrap().start as u64)).await?;
-            file.write_all(response.body.unwrap().as_slice()).await?;
-        }
+        let netrc = Netrc::parse("machine 127.0.0.1 login alice password secret\ndefault login anonymous password guest\n").unwrap();
+        let client = Client::new().set_netrc(netrc);
+        let connection = client.connect(&format!("http://{}/", address)).await.unwrap();
+        assert_eq!(connection.request(Request::new()).await.unwrap().status_code, 200);
 
-        }else {
-            let get_request = Request::new();
-            let get_response = self.request(get_request).await?;
-            file.write_all(get_response.body.unwrap().as_slice()).await?;
-        }
+        let netrc = Netrc::parse("default login alice password secret\n").unwrap();
+        let connection = Client::new().set_netrc(netrc).connect(&format!("http://{}/", address)).await.unwrap();
+        assert_eq!(connection.request(Request::new()).await.unwrap().status_code, 403);
+    }
+
+    #[tokio::test]
+    async fn uploads_follow_the_limiter_of_the_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut head = vec![];
+                while !head.ends_with(b"\r\n\r\n") {
+                    match socket.read_u8().await {
+                        Ok(byte) => head.push(byte),
+                        Err(_) => break,
+                    }
+                }
+                let mut body = vec![0u8; 20_000];
+                let _ = socket.read_exact(&mut body).await;
+                let _ = socket.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await;
+            }
+        });
+
+        // The client alone would take hours for the body.
+        let client = Client::new().set_rate_limiter(RateLimiter::new(1));
+        let connection = client.connect(&format!("http://{}/", address)).await.unwrap();
+        let request = Request::new()
+            .set_body(vec![0u8; 20_000])
+            .set_rate_limiter(RateLimiter::new(40_000));
+
+        let started = Instant::now();
+        let response = tokio::time::timeout(Duration::from_secs(5), connection.request(request)).await.unwrap();
+        assert_eq!(response.unwrap().status_code, 200);
+        assert!(started.elapsed() >= Duration::from_millis(400), "{:?}", started.elapsed());
+    }
 
-        println!("File downloaded: {}", file_path.to_str().unwrap());
+    #[tokio::test]
+    async fn failed_handshakes_are_tls_errors() {
+        // A plain HTTP server on an https URL.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let _ = socket.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n").await;
+            }
+        });
 
-        Ok(())
+        let connection = Connection::new(&format!("https://localhost:{}/", address.port())).await.unwrap();
+        assert!(matches!(connection.request(Request::new()).await, Err(Error::TlsError(_))));
+    }
+
+    #[tokio::test]
+    async fn invalid_host_names_are_url_errors() {
+        assert!(matches!(Connection::new("https://exa mple.com/").await, Err(Error::UrlParsingError)));
+        assert!(matches!(Connection::new("http://example.com:port/").await, Err(Error::UrlParsingError)));
     }
 
-}
\ No newline at end of file
+}
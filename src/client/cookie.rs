@@ -0,0 +1,495 @@
+use std::{path::Path, sync::{Arc, Mutex}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use crate::app::error::Error;
+use super::{public_suffix::PublicSuffixList, response::Response, url::ParsedUrl};
+
+/// First line of a cookie file, the one curl and browsers write.
+static FILE_HEADER: &str = "# Netscape HTTP Cookie File";
+/// Prefix of the lines of HttpOnly cookies in a cookie file.
+static HTTP_ONLY_PREFIX: &str = "#HttpOnly_";
+
+/// Whether a cookie is sent with requests started by other sites. Kept
+/// for persistence, this client sends every cookie that matches.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None
+}
+
+/// A cookie a server set with `Set-Cookie`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    /// Lowercase, without a leading dot.
+    pub domain: String,
+    /// Set when the cookie had no `Domain` attribute: it is then only sent
+    /// to exactly `domain`, not to its subdomains.
+    pub host_only: bool,
+    pub path: String,
+    /// `None` for session cookies.
+    pub expires: Option<SystemTime>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<SameSite>,
+    /// Orders cookies with paths of the same length, older ones first.
+    creation: u64
+}
+
+impl Cookie {
+
+    /// Parses a `Set-Cookie` value received from `url` as RFC 6265 section
+    /// 5.2 describes. Returns `None` for values a user agent has to ignore.
+    pub fn parse(set_cookie: &str, url: &ParsedUrl) -> Option<Cookie> {
+        let now = SystemTime::now();
+        let mut parts = set_cookie.split(';');
+        let (name, value) = parts.next()?.split_once('=')?;
+        let (name, value) = (name.trim(), value.trim());
+        if name.is_empty() {
+            return None;
+        }
+
+        let host = url.host.to_lowercase();
+        let mut cookie = Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(&url.path),
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+            creation: 0
+        };
+
+        let mut max_age = None;
+        for attribute in parts {
+            let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+            let (key, value) = (key.trim().to_lowercase(), value.trim());
+
+            match key.as_str() {
+                "expires" => cookie.expires = parse_date(value).or(cookie.expires),
+                "max-age" => {
+                    if let Ok(seconds) = value.parse::<i64>() {
+                        max_age = Some(if seconds <= 0 {
+                            UNIX_EPOCH
+                        }else {
+                            now + Duration::from_secs(seconds as u64)
+                        });
+                    }
+                },
+                "domain" if !value.is_empty() => {
+                    cookie.domain = value.trim_start_matches('.').to_lowercase();
+                    cookie.host_only = false;
+                },
+                "path" if value.starts_with('/') => cookie.path = value.to_string(),
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                "samesite" => cookie.same_site = match value.to_lowercase().as_str() {
+                    "strict" => Some(SameSite::Strict),
+                    "lax" => Some(SameSite::Lax),
+                    "none" => Some(SameSite::None),
+                    _ => cookie.same_site,
+                },
+                _ => {},
+            }
+        }
+        // Max-Age wins over Expires, whatever their order.
+        cookie.expires = max_age.or(cookie.expires);
+
+        if !cookie.host_only && !domain_match(&host, &cookie.domain) {
+            return None;
+        }
+        // Only secure origins set secure cookies, and the prefixes promise
+        // how the cookie was set.
+        let secure_origin = url.scheme == "https";
+        if cookie.secure && !secure_origin {
+            return None;
+        }
+        if cookie.name.starts_with("__Secure-") && !cookie.secure {
+            return None;
+        }
+        if cookie.name.starts_with("__Host-") && (!cookie.secure || !cookie.host_only || cookie.path != "/") {
+            return None;
+        }
+        Some(cookie)
+    }
+
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    /// Whether the cookie goes along with a request to `url`.
+    pub fn matches(&self, url: &ParsedUrl) -> bool {
+        let host = url.host.to_lowercase();
+        let domain_matches = if self.host_only { host == self.domain } else { domain_match(&host, &self.domain) };
+        let path = url.path.split(['?', '#']).next().unwrap_or_default();
+        domain_matches && path_match(path, &self.path) && (!self.secure || url.scheme == "https")
+    }
+
+}
+
+/// "Domain-match" of RFC 6265 section 5.1.3.
+fn domain_match(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.ends_with(domain) && host[..host.len() - domain.len()].ends_with('.') && host.parse::<std::net::IpAddr>().is_err())
+}
+
+/// "Path-match" of RFC 6265 section 5.1.4.
+fn path_match(path: &str, cookie_path: &str) -> bool {
+    path == cookie_path
+        || (path.starts_with(cookie_path) && (cookie_path.ends_with('/') || path[cookie_path.len()..].starts_with('/')))
+}
+
+/// The directory of the request path, used when a cookie has no `Path`.
+fn default_path(url_path: &str) -> String {
+    let path = url_path.split(['?', '#']).next().unwrap_or_default();
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(index) => path[..index].to_string(),
+    }
+}
+
+/// Parses a cookie date the lenient way RFC 6265 section 5.1.1 describes,
+/// which accepts every format servers are known to send.
+fn parse_date(value: &str) -> Option<SystemTime> {
+    let (mut time, mut day, mut month, mut year) = (None, None, None, None);
+    let months = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+
+    let delimiter = |c: char| matches!(c, '\t' | ' '..='/' | ';'..='@' | '['..='`' | '{'..='~');
+    for token in value.split(delimiter).filter(|token| !token.is_empty()) {
+        let digits = token.chars().take_while(char::is_ascii_digit).count();
+        let lower = token.to_lowercase();
+
+        if time.is_none() {
+            let fields: Vec<&str> = token.splitn(3, ':').collect();
+            if fields.len() == 3 {
+                let parse = |field: &str| -> Option<u64> {
+                    let digits: String = field.chars().take_while(char::is_ascii_digit).collect();
+                    (1..=2).contains(&digits.len()).then(|| digits.parse().ok()).flatten()
+                };
+                if let (Some(h), Some(m), Some(s)) = (parse(fields[0]), parse(fields[1]), parse(fields[2])) {
+                    time = Some((h, m, s));
+                    continue;
+                }
+            }
+        }
+        if day.is_none() && (1..=2).contains(&digits) {
+            day = token[..digits].parse::<u64>().ok();
+            continue;
+        }
+        if month.is_none() {
+            if let Some(index) = months.iter().position(|month| lower.starts_with(month)) {
+                month = Some(index as u64 + 1);
+                continue;
+            }
+        }
+        if year.is_none() && (2..=4).contains(&digits) {
+            year = token[..digits].parse::<u64>().ok();
+        }
+    }
+
+    let (hour, minute, second) = time?;
+    let (day, month, mut year) = (day?, month?, year?);
+    year += match year {
+        70..=99 => 1900,
+        0..=69 => 2000,
+        _ => 0,
+    };
+    if !(1..=31).contains(&day) || year < 1601 || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+
+    let seconds = days_since_epoch(year, month, day) * 86_400 + (hour * 3600 + minute * 60 + second) as i64;
+    if seconds < 0 {
+        Some(UNIX_EPOCH)
+    }else {
+        Some(UNIX_EPOCH + Duration::from_secs(seconds as u64))
+    }
+}
+
+/// Days from 1970-01-01 to the given date of the proleptic Gregorian
+/// calendar.
+fn days_since_epoch(year: u64, month: u64, day: u64) -> i64 {
+    let (year, month, day) = (year as i64, month as i64, day as i64);
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[derive(Debug)]
+struct Store {
+    cookies: Vec<Cookie>,
+    public_suffixes: PublicSuffixList,
+    next_creation: u64
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Store {
+            cookies: vec![],
+            public_suffixes: PublicSuffixList::system().unwrap_or_else(PublicSuffixList::bundled),
+            next_creation: 0
+        }
+    }
+}
+
+/// Keeps the cookies servers set and sends them back with later requests.
+///
+/// Set it on a `Client` and every request of its connections, redirects
+/// included, takes part. Clones share the same cookies.
+#[derive(Clone, Debug, Default)]
+pub struct CookieJar {
+    store: Arc<Mutex<Store>>
+}
+
+impl CookieJar {
+
+    /// A jar that refuses cookies for public suffixes, after the list
+    /// installed with the system or, without one, the bundled list.
+    pub fn new() -> CookieJar {
+        CookieJar::default()
+    }
+
+    /// Refuses cookies for the public suffixes in `list`, e.g. a cookie of
+    /// `a.example.co.uk` for all of `co.uk`. With `PublicSuffixList::default()`
+    /// only top-level domains are refused.
+    pub fn set_public_suffixes(self, list: PublicSuffixList) -> CookieJar {
+        self.store.lock().unwrap().public_suffixes = list;
+        self
+    }
+
+    /// Stores the cookies of every `Set-Cookie` header of `response`.
+    pub fn store(&self, url: &ParsedUrl, response: &Response) {
+        for set_cookie in response.header_all("Set-Cookie") {
+            self.add(url, set_cookie);
+        }
+    }
+
+    /// Stores the cookie of a `Set-Cookie` value received from `url` and
+    /// returns whether it was accepted. An expired cookie is accepted and
+    /// removes the one it replaces.
+    pub fn add(&self, url: &ParsedUrl, set_cookie: &str) -> bool {
+        let Some(mut cookie) = Cookie::parse(set_cookie, url) else { return false };
+
+        let mut store = self.store.lock().unwrap();
+        if !cookie.host_only && store.public_suffixes.is_public_suffix(&cookie.domain) {
+            // A public suffix may still set a cookie for itself.
+            if cookie.domain != url.host.to_lowercase() {
+                return false;
+            }
+            cookie.host_only = true;
+        }
+
+        store.insert(cookie);
+        true
+    }
+
+    /// The value of the `Cookie` header for a request to `url`: longer
+    /// paths first, then older cookies first.
+    pub fn header(&self, url: &ParsedUrl) -> Option<String> {
+        let now = SystemTime::now();
+        let mut store = self.store.lock().unwrap();
+        store.cookies.retain(|cookie| !cookie.is_expired(now));
+
+        let mut cookies: Vec<&Cookie> = store.cookies.iter().filter(|cookie| cookie.matches(url)).collect();
+        if cookies.is_empty() {
+            return None;
+        }
+        cookies.sort_by(|a, b| b.path.len().cmp(&a.path.len()).then(a.creation.cmp(&b.creation)));
+
+        let pairs: Vec<String> = cookies.iter().map(|cookie| format!("{}={}", cookie.name, cookie.value)).collect();
+        Some(pairs.join("; "))
+    }
+
+    /// Every cookie that has not expired.
+    pub fn cookies(&self) -> Vec<Cookie> {
+        let now = SystemTime::now();
+        self.store.lock().unwrap().cookies.iter().filter(|cookie| !cookie.is_expired(now)).cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.store.lock().unwrap().cookies.clear();
+    }
+
+    /// Adds the cookies of a file in the Netscape format curl and browser
+    /// extensions read and write.
+    pub async fn load(&self, path: &Path) -> Result<(), Error> {
+        let content = tokio::fs::read_to_string(path).await?;
+        let mut store = self.store.lock().unwrap();
+        for line in content.lines() {
+            let (line, http_only) = match line.strip_prefix(HTTP_ONLY_PREFIX) {
+                Some(line) => (line, true),
+                None => (line, false),
+            };
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != 7 {
+                return Err(Error::CookieParsingError);
+            }
+            let expires: u64 = fields[4].parse().map_err(|_| Error::CookieParsingError)?;
+            store.insert(Cookie {
+                name: fields[5].to_string(),
+                value: fields[6].to_string(),
+                domain: fields[0].trim_start_matches('.').to_lowercase(),
+                host_only: fields[1] != "TRUE",
+                path: fields[2].to_string(),
+                expires: (expires > 0).then(|| UNIX_EPOCH + Duration::from_secs(expires)),
+                secure: fields[3] == "TRUE",
+                http_only,
+                same_site: None,
+                creation: 0
+            });
+        }
+        Ok(())
+    }
+
+    /// Writes every cookie, session cookies included, to a file in the
+    /// Netscape format.
+    pub async fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut content = format!("{}\n\n", FILE_HEADER);
+        for cookie in self.cookies() {
+            let expires = cookie.expires
+                .and_then(|expires| expires.duration_since(UNIX_EPOCH).ok())
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0);
+            content.push_str(&format!("{}{}{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                if cookie.http_only { HTTP_ONLY_PREFIX } else { "" },
+                if cookie.host_only { "" } else { "." },
+                cookie.domain,
+                if cookie.host_only { "FALSE" } else { "TRUE" },
+                cookie.path,
+                if cookie.secure { "TRUE" } else { "FALSE" },
+                expires,
+                cookie.name,
+                cookie.value));
+        }
+
+        let mut temporary = path.as_os_str().to_os_string();
+        temporary.push(".tmp");
+        tokio::fs::write(&temporary, content).await?;
+        tokio::fs::rename(&temporary, path).await?;
+        Ok(())
+    }
+
+}
+
+impl Store {
+
+    /// Adds `cookie`, replacing the one with the same name, domain and path
+    /// but keeping its creation order.
+    fn insert(&mut self, mut cookie: Cookie) {
+        let existing = self.cookies.iter()
+            .position(|c| c.name == cookie.name && c.domain == cookie.domain && c.path == cookie.path);
+        match existing {
+            Some(index) => {
+                cookie.creation = self.cookies[index].creation;
+                self.cookies.remove(index);
+            },
+            None => {
+                cookie.creation = self.next_creation;
+                self.next_creation += 1;
+            },
+        }
+
+        if !cookie.is_expired(SystemTime::now()) {
+            self.cookies.push(cookie);
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::client::{public_suffix::PublicSuffixList, url::ParsedUrl};
+    use super::{parse_date, Cookie, CookieJar, SameSite};
+
+    fn url(url: &str) -> ParsedUrl {
+        ParsedUrl::from(url).unwrap()
+    }
+
+    #[test]
+    fn parses_attributes() {
+        let cookie = Cookie::parse(
+            "sid=abc; Domain=.Example.com; Path=/app; Secure; HttpOnly; SameSite=Lax; Max-Age=60; Expires=Thu, 01 Jan 1970 00:00:00 GMT",
+            &url("https://www.example.com/login")
+        ).unwrap();
+
+        assert_eq!((cookie.name.as_str(), cookie.value.as_str()), ("sid", "abc"));
+        assert_eq!(cookie.domain, "example.com");
+        assert!(!cookie.host_only && cookie.secure && cookie.http_only);
+        assert_eq!(cookie.path, "/app");
+        assert_eq!(cookie.same_site, Some(SameSite::Lax));
+        assert!(!cookie.is_expired(std::time::SystemTime::now()));
+
+        let cookie = Cookie::parse("a=b", &url("http://example.com/docs/page.html")).unwrap();
+        assert!(cookie.host_only && cookie.expires.is_none());
+        assert_eq!(cookie.path, "/docs");
+
+        assert!(Cookie::parse("a=b; Domain=other.com", &url("http://example.com/")).is_none());
+        assert!(Cookie::parse("a=b; Secure", &url("http://example.com/")).is_none());
+        assert!(Cookie::parse("no_value", &url("http://example.com/")).is_none());
+    }
+
+    #[test]
+    fn parses_cookie_dates() {
+        let expected = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        assert_eq!(parse_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(expected));
+        assert_eq!(parse_date("Sunday, 06-Nov-94 08:49:37 GMT"), Some(expected));
+        assert_eq!(parse_date("Sun Nov  6 08:49:37 1994"), Some(expected));
+        assert_eq!(parse_date("Tue, 29 Feb 2028 00:00:00 GMT"), Some(UNIX_EPOCH + Duration::from_secs(1_835_395_200)));
+        assert_eq!(parse_date("not a date"), None);
+    }
+
+    #[test]
+    fn jar_matches_domains_paths_and_suffixes() {
+        let jar = CookieJar::new().set_public_suffixes(PublicSuffixList::parse("com\nuk\nco.uk\n"));
+        let origin = url("https://www.example.co.uk/app/login");
+        assert!(jar.add(&origin, "host=1"));
+        assert!(jar.add(&origin, "domain=2; Domain=example.co.uk; Path=/"));
+        assert!(jar.add(&origin, "deep=3; Path=/app/files"));
+        assert!(!jar.add(&origin, "suffix=4; Domain=co.uk"));
+
+        assert_eq!(jar.header(&url("https://www.example.co.uk/app/files/a.zip")).as_deref(), Some("deep=3; host=1; domain=2"));
+        assert_eq!(jar.header(&url("https://cdn.example.co.uk/app")).as_deref(), Some("domain=2"));
+        assert_eq!(jar.header(&url("https://www.example.co.uk/application")).as_deref(), Some("domain=2"));
+
+        assert!(jar.add(&origin, "host=gone; Max-Age=0"));
+        assert_eq!(jar.header(&url("https://www.example.co.uk/app")).as_deref(), Some("domain=2"));
+    }
+
+    #[test]
+    fn new_jar_knows_public_suffixes() {
+        let origin = url("https://www.example.co.uk/");
+        assert!(!CookieJar::new().add(&origin, "suffix=1; Domain=co.uk"));
+        assert!(CookieJar::new().set_public_suffixes(PublicSuffixList::default()).add(&origin, "suffix=1; Domain=co.uk"));
+    }
+
+    #[tokio::test]
+    async fn jar_survives_a_file() {
+        let path = std::env::temp_dir().join(format!("http_client_cookies_{}.txt", std::process::id()));
+        let jar = CookieJar::new();
+        jar.add(&url("https://example.com/"), "session=1; HttpOnly");
+        jar.add(&url("https://example.com/"), "kept=2; Domain=example.com; Max-Age=3600; Secure");
+        jar.save(&path).await.unwrap();
+
+        let loaded = CookieJar::new();
+        loaded.load(&path).await.unwrap();
+        tokio::fs::remove_file(&path).await.unwrap();
+
+        assert_eq!(loaded.cookies().len(), 2);
+        assert_eq!(loaded.header(&url("https://www.example.com/")).as_deref(), Some("kept=2"));
+        assert_eq!(loaded.header(&url("http://example.com/")).as_deref(), Some("session=1"));
+    }
+
+}
@@ -1,6 +1,10 @@
-use std::fmt::Display;
+use std::{fmt::Display, str::FromStr};
 
-#[derive(Clone)]
+use crate::app::error::Error;
+
+/// A request method. Parses case-insensitively from its name.
+#[derive(Clone, Debug, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Method {
     GET,
     POST,
@@ -23,4 +27,20 @@ impl Display for Method {
             Method::DELETE => write!(f, "DELETE"),
         }
     }
-}
\ No newline at end of file
+}
+impl FromStr for Method {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "GET" => Ok(Method::GET),
+            "POST" => Ok(Method::POST),
+            "PUT" => Ok(Method::PUT),
+            "PATCH" => Ok(Method::PATCH),
+            "HEAD" => Ok(Method::HEAD),
+            "OPTIONS" => Ok(Method::OPTIONS),
+            "DELETE" => Ok(Method::DELETE),
+            _ => Err(Error::MethodParsingError),
+        }
+    }
+}
@@ -1,5 +1,24 @@
 pub mod url;
+pub mod auth;
 pub mod connection;
 pub mod response;
 pub mod method;
 pub mod request;
+#[allow(clippy::module_inception)]
+pub mod client;
+pub mod throttle;
+pub mod tls;
+pub mod stream;
+pub mod cookie;
+pub mod netrc;
+pub mod proxy;
+pub mod public_suffix;
+pub mod resolve;
+
+pub use client::Client;
+pub use connection::Connection;
+pub use cookie::CookieJar;
+pub use method::Method;
+pub use request::Request;
+pub use response::Response;
+pub use url::ParsedUrl;
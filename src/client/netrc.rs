@@ -0,0 +1,167 @@
+use std::path::{Path, PathBuf};
+
+use crate::app::error::Error;
+use super::auth::Credentials;
+
+#[derive(Clone, Debug, PartialEq)]
+struct Machine {
+    /// `None` for the `default` entry.
+    host: Option<String>,
+    login: Option<String>,
+    password: Option<String>
+}
+
+/// Credentials per host from a `.netrc` file, the way curl, wget and ftp
+/// read it: `machine`, `login`, `password` and `default` are used,
+/// `account` and `macdef` are skipped.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Netrc {
+    machines: Vec<Machine>
+}
+
+impl Netrc {
+
+    pub fn parse(content: &str) -> Result<Netrc, Error> {
+        let mut netrc = Netrc::default();
+        let mut lines = content.lines();
+
+        while let Some(line) = lines.next() {
+            if line.trim_start().starts_with('#') {
+                continue;
+            }
+
+            let mut tokens = tokenize(line).into_iter();
+            while let Some(token) = tokens.next() {
+                match token.as_str() {
+                    "machine" => {
+                        let host = tokens.next().ok_or(Error::NetrcParsingError)?;
+                        netrc.machines.push(Machine { host: Some(host.to_lowercase()), login: None, password: None });
+                    },
+                    "default" => netrc.machines.push(Machine { host: None, login: None, password: None }),
+                    "login" | "password" | "account" => {
+                        let value = tokens.next().ok_or(Error::NetrcParsingError)?;
+                        let machine = netrc.machines.last_mut().ok_or(Error::NetrcParsingError)?;
+                        match token.as_str() {
+                            "login" => machine.login = Some(value),
+                            "password" => machine.password = Some(value),
+                            _ => {},
+                        }
+                    },
+                    "macdef" => {
+                        // The macro runs until the next empty line.
+                        for line in lines.by_ref() {
+                            if line.trim().is_empty() {
+                                break;
+                            }
+                        }
+                        break;
+                    },
+                    _ => return Err(Error::NetrcParsingError),
+                }
+            }
+        }
+        Ok(netrc)
+    }
+
+    pub async fn load(path: &Path) -> Result<Netrc, Error> {
+        Self::parse(&tokio::fs::read_to_string(path).await?)
+    }
+
+    /// `.netrc` in the home directory, or `_netrc` on Windows.
+    pub fn default_path() -> Option<PathBuf> {
+        let (variable, name) = if cfg!(windows) { ("USERPROFILE", "_netrc") } else { ("HOME", ".netrc") };
+        std::env::var_os(variable).map(|home| PathBuf::from(home).join(name))
+    }
+
+    /// The credentials of the first entry for `host`, or of the `default`
+    /// entry when there is none.
+    pub fn credentials(&self, host: &str) -> Option<Credentials> {
+        let host = host.to_lowercase();
+        self.machines.iter()
+            .find(|machine| machine.host.as_deref() == Some(host.as_str()))
+            .or_else(|| self.machines.iter().find(|machine| machine.host.is_none()))
+            .and_then(Machine::credentials)
+    }
+
+    /// The credentials of the first `machine` entry naming `host`, leaving
+    /// out the `default` entry.
+    pub fn machine_credentials(&self, host: &str) -> Option<Credentials> {
+        let host = host.to_lowercase();
+        self.machines.iter()
+            .find(|machine| machine.host.as_deref() == Some(host.as_str()))
+            .and_then(Machine::credentials)
+    }
+
+}
+
+impl Machine {
+
+    fn credentials(&self) -> Option<Credentials> {
+        let login = self.login.as_deref()?;
+        Some(Credentials::new(login, self.password.as_deref().unwrap_or_default()))
+    }
+
+}
+
+/// Splits a line at whitespace. Double quotes keep whitespace in a token
+/// and backslashes escape the next character in them.
+fn tokenize(line: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut chars = line.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let mut token = String::new();
+        if c == '"' {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => token.extend(chars.next()),
+                    '"' => break,
+                    c => token.push(c),
+                }
+            }
+        }else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                token.push(c);
+            }
+        }
+        tokens.push(token);
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod test {
+    use super::Netrc;
+
+    #[test]
+    fn finds_machines_and_default() {
+        let netrc = Netrc::parse(concat!(
+            "# mirrors\n",
+            "machine mirror.example.com login bob password \"two words\"\n",
+            "machine Other.example.com\n",
+            "  login alice\n",
+            "  password s3cret\n",
+            "macdef init\n",
+            "cd /pub\n",
+            "\n",
+            "default login anonymous password guest@\n"
+        )).unwrap();
+
+        let bob = netrc.credentials("mirror.example.com").unwrap();
+        assert_eq!((bob.username.as_str(), bob.password.as_str()), ("bob", "two words"));
+        assert_eq!(netrc.credentials("other.EXAMPLE.com").unwrap().username, "alice");
+        assert_eq!(netrc.credentials("unknown.example.com").unwrap().password, "guest@");
+        assert_eq!(netrc.machine_credentials("unknown.example.com"), None);
+
+        assert!(Netrc::parse("login bob").is_err());
+        assert!(Netrc::parse("machine").is_err());
+        assert_eq!(Netrc::parse("machine a.example.com").unwrap().credentials("a.example.com"), None);
+    }
+
+}
@@ -0,0 +1,119 @@
+use std::{collections::HashSet, path::Path};
+
+use crate::app::error::Error;
+
+/// Where most Linux distributions install the list.
+static SYSTEM_LIST: &str = "/usr/share/publicsuffix/public_suffix_list.dat";
+
+/// A copy of the whole list from publicsuffix.org, for systems that do not
+/// install one.
+static BUNDLED_LIST: &str = include_str!("public_suffix_list.dat");
+
+/// The rules of the Public Suffix List (<https://publicsuffix.org>), which
+/// tell the domains anyone can register names under, like `com` or
+/// `co.uk`. Cookies are not allowed to be set for those.
+///
+/// An empty list knows no rules, so only top-level domains count as public
+/// suffixes. Names are matched as given: internationalized names in the
+/// list are in Unicode, hosts of URLs usually in Punycode.
+#[derive(Clone, Debug, Default)]
+pub struct PublicSuffixList {
+    rules: HashSet<String>,
+    /// Domains of the `*.` rules, e.g. `ck` for `*.ck`.
+    wildcards: HashSet<String>,
+    /// Domains of the `!` rules, e.g. `www.ck` for `!www.ck`.
+    exceptions: HashSet<String>
+}
+
+impl PublicSuffixList {
+
+    /// Parses the list in the format of `public_suffix_list.dat`.
+    pub fn parse(list: &str) -> PublicSuffixList {
+        let mut suffixes = PublicSuffixList::default();
+        for line in list.lines() {
+            let rule = line.split_whitespace().next().unwrap_or_default().to_lowercase();
+            if rule.is_empty() || rule.starts_with("//") {
+                continue;
+            }
+
+            if let Some(exception) = rule.strip_prefix('!') {
+                suffixes.exceptions.insert(exception.to_string());
+            }else if let Some(wildcard) = rule.strip_prefix("*.") {
+                suffixes.wildcards.insert(wildcard.to_string());
+            }else {
+                suffixes.rules.insert(rule);
+            }
+        }
+        suffixes
+    }
+
+    pub async fn load(path: &Path) -> Result<PublicSuffixList, Error> {
+        Ok(Self::parse(&tokio::fs::read_to_string(path).await?))
+    }
+
+    /// The copy installed with the system, if there is one. It is read
+    /// once when a cookie jar is made, so this blocks.
+    pub fn system() -> Option<PublicSuffixList> {
+        std::fs::read_to_string(SYSTEM_LIST).ok().map(|list| Self::parse(&list))
+    }
+
+    /// The copy of the list that ships with the crate. The one of the system
+    /// is usually more recent.
+    pub fn bundled() -> PublicSuffixList {
+        Self::parse(BUNDLED_LIST)
+    }
+
+    /// The public suffix `domain` ends with, following the algorithm of the
+    /// list: exceptions win, then the longest matching rule, and without a
+    /// match the last label.
+    pub fn suffix<'a>(&self, domain: &'a str) -> &'a str {
+        let domain = domain.trim_end_matches('.');
+        let mut starts = vec![0];
+        starts.extend(domain.match_indices('.').map(|(i, _)| i + 1));
+
+        for (index, start) in starts.iter().enumerate() {
+            let candidate = &domain[*start..];
+            let parent = starts.get(index + 1).map(|next| &domain[*next..]);
+
+            if self.exceptions.contains(&candidate.to_lowercase()) {
+                return parent.unwrap_or(candidate);
+            }
+            if self.rules.contains(&candidate.to_lowercase()) {
+                return candidate;
+            }
+            if parent.is_some_and(|parent| self.wildcards.contains(&parent.to_lowercase())) {
+                return candidate;
+            }
+        }
+        &domain[*starts.last().unwrap()..]
+    }
+
+    pub fn is_public_suffix(&self, domain: &str) -> bool {
+        let domain = domain.trim_end_matches('.');
+        self.suffix(domain).len() == domain.len()
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::PublicSuffixList;
+
+    #[test]
+    fn rules_wildcards_and_exceptions() {
+        let list = PublicSuffixList::parse("// comment\ncom\nuk\nco.uk\n*.ck\n!www.ck\n");
+
+        assert_eq!(list.suffix("example.com"), "com");
+        assert_eq!(list.suffix("www.example.co.uk"), "co.uk");
+        assert_eq!(list.suffix("shop.example.ck"), "example.ck");
+        assert_eq!(list.suffix("www.ck"), "ck");
+        assert_eq!(list.suffix("example.unlisted"), "unlisted");
+        assert!(list.is_public_suffix("co.uk"));
+        assert!(!list.is_public_suffix("example.co.uk"));
+        assert!(PublicSuffixList::default().is_public_suffix("com"));
+        let bundled = PublicSuffixList::bundled();
+        assert!(bundled.is_public_suffix("co.uk") && bundled.is_public_suffix("com.pl") && bundled.is_public_suffix("netlify.app"));
+        assert_eq!(bundled.suffix("shop.example.ck"), "example.ck");
+    }
+
+}
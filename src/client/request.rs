@@ -1,22 +1,28 @@
 use std::{collections::HashMap, ops::Range, fmt::Display, io::Write};
 
-use super::method::Method;
+use tokio_util::sync::CancellationToken;
+
+use super::{auth::Credentials, method::Method, throttle::RateLimiter};
 
 static BOUNDARY: &str = "X_HTTPCLIENT_BOUNDARY";
 
+/// An HTTP request, built with chained setters and sent by `Connection`.
 #[derive(Clone)]
 pub struct Request {
     method: Method,
     headers: HashMap<String, String>,
     query_strings: String,
     range: Option<Range<usize>>,
-    body: Option<Vec<u8>>
+    body: Option<Vec<u8>>,
+    cancellation: Option<CancellationToken>,
+    credentials: Option<Credentials>,
+    rate_limiter: Option<RateLimiter>
 }
 
 impl Request {
 
     pub fn new() -> Request {
-        Request { method: Method::GET, headers: HashMap::new(), query_strings: String::new(), range: None, body: None }
+        Request { method: Method::GET, headers: HashMap::new(), query_strings: String::new(), range: None, body: None, cancellation: None, credentials: None, rate_limiter: None }
     }
 
     pub fn set_method(mut self, method: Method) -> Request {
@@ -33,10 +39,23 @@ impl Request {
         self
     }
 
+    pub fn add_header<T: Display>(mut self, key: &str, value: T) -> Request {
+        self.headers.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Removes a header, whatever the case of its name.
+    pub fn remove_header(mut self, key: &str) -> Request {
+        self.headers.retain(|name, _| !name.eq_ignore_ascii_case(key));
+        self
+    }
+
     pub fn get_headers(&self) -> &HashMap<String, String> {
         &self.headers
     }
 
+    /// Sets the `Range` header. The end of the range is inclusive, exactly as
+    /// it is written on the wire (`bytes=start-end`).
     pub fn set_range(mut self, range: Range<usize>) -> Request {
         self.range = Some(range);
         self
@@ -46,10 +65,71 @@ impl Request {
         &self.range
     }
 
+    /// Aborts the request with `Error::Cancelled` as soon as `token` is
+    /// cancelled, closing the connection.
+    pub fn set_cancellation(mut self, token: CancellationToken) -> Request {
+        self.cancellation = Some(token);
+        self
+    }
+
+    pub fn get_cancellation(&self) -> Option<&CancellationToken> {
+        self.cancellation.as_ref()
+    }
+
+    /// Limits the upload of the body, in place of the limiter of the client.
+    pub fn set_rate_limiter(mut self, rate_limiter: RateLimiter) -> Request {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    pub fn get_rate_limiter(&self) -> Option<&RateLimiter> {
+        self.rate_limiter.as_ref()
+    }
+
+    /// Sends `Authorization: Basic` right away, without waiting for a
+    /// challenge.
+    pub fn basic_auth(self, username: &str, password: &str) -> Request {
+        let authorization = Credentials::new(username, password).basic();
+        self.remove_header("Authorization").add_header("Authorization", authorization)
+    }
+
+    pub fn bearer_auth(self, token: &str) -> Request {
+        self.remove_header("Authorization").add_header("Authorization", format!("Bearer {}", token))
+    }
+
+    /// Answers a `401` challenge with these credentials, by Digest or Basic
+    /// as the server asks. They take precedence over the userinfo of the URL.
+    pub fn set_credentials(mut self, credentials: Credentials) -> Request {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    pub fn get_credentials(&self) -> Option<&Credentials> {
+        self.credentials.as_ref()
+    }
+
+    /// Drops everything that authenticates the request, for a redirect to
+    /// another origin.
+    pub fn strip_credentials(mut self) -> Request {
+        self.credentials = None;
+        self.remove_header("Authorization").remove_header("Cookie")
+    }
+
     pub fn get_query_strings(&self) -> &String {
         &self.query_strings
     }
 
+    /// Sends `body` as it is. Set a matching `Content-Type` header with it.
+    pub fn set_body(mut self, body: Vec<u8>) -> Request {
+        self.body = Some(body);
+        self
+    }
+
+    pub fn clear_body(mut self) -> Request {
+        self.body = None;
+        self
+    }
+
     pub fn get_body(&self) -> &Option<Vec<u8>> {
         &self.body
     }
@@ -121,53 +201,84 @@ impl Request {
         self
     }
 
-    /**
+    /*
      * --X_HTTPCLIENT_BOUNDARY
      * Content-Disposition: form-data; name="name"
      * 
      * value
      * --X_HTTPCLIENT_BOUNDARY
-     * * --X_HTTPCLIENT_BOUNDARY
-     * Content-Disposition: form-data; name="name"
+     * Content-Disposition: form-data; name="file"; filename="a.txt"
+     * Content-Type: application/octet-stream
      * 
-     * value
+     * bytes
      * --X_HTTPCLIENT_BOUNDARY--
+     *
+     * The body always ends with the closing boundary; adding a part turns it
+     * back into a separator first.
      */
 
-    pub fn add_data<T: Display>(mut self, key: &str, value: T) -> Request {
-        let mut item = String::new();
-        item.push_str(&format!(
-            "Content-Disposition: form-data; name=\"{}\"\r\n\r\n", key
-        ));
-        item.push_str(&format!("{}\r\n", value.to_string()));
-        item.push_str(&format!("--{}\r\n", BOUNDARY));
+    pub fn add_data<T: Display>(self, key: &str, value: T) -> Request {
+        let headers = format!("Content-Disposition: form-data; name=\"{}\"\r\n\r\n", key);
+        self.add_part(&headers, value.to_string().as_bytes())
+    }
 
-        if let Some(mut body) = self.body {
-            body.write_all(item.as_bytes()).unwrap();
-            self.body = Some(body);
-        }else {
-            item.insert_str(0, &format!("--{}\r\n", BOUNDARY));
-            self.body = Some(item.as_bytes().to_vec());
-        }
+    pub fn add_file(self, key: &str, file_name: &str, bytes: Vec<u8>) -> Request {
+        let headers = format!(
+            "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: application/octet-stream\r\n\r\n",
+            key, file_name
+        );
+        self.add_part(&headers, &bytes)
+    }
 
+    fn add_part(mut self, headers: &str, content: &[u8]) -> Request {
+        let closing = format!("--{}--\r\n", BOUNDARY);
+
+        let mut body = match self.body {
+            Some(mut body) if body.ends_with(closing.as_bytes()) => {
+                body.truncate(body.len() - 4);
+                body.extend_from_slice(b"\r\n");
+                body
+            },
+            _ => format!("--{}\r\n", BOUNDARY).into_bytes(),
+        };
+
+        body.write_all(headers.as_bytes()).unwrap();
+        body.write_all(content).unwrap();
+        body.write_all(format!("\r\n{}", closing).as_bytes()).unwrap();
+        self.body = Some(body);
         self
     }
 
-    pub fn add_file(mut self, key: &str, file_name: &str, bytes: Vec<u8>) -> Request {
-        let item = format!("Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n\r\n", key, file_name);
+}
 
-        if let Some(mut body) = self.body {
-            body.write_all(item.as_bytes()).unwrap();
-            body.write_all(&bytes).unwrap();
-            body.write_all(format!("\r\n--{}\r\n", BOUNDARY).as_bytes()).unwrap();
-            self.body = Some(body);
-        }else {
-            let mut body = format!("--{}\r\n", BOUNDARY).as_bytes().to_vec();
-            body.write_all(item.as_bytes()).unwrap();
-            body.write_all(&bytes).unwrap();
-            body.write_all(format!("\r\n--{}\r\n", BOUNDARY).as_bytes()).unwrap();
-        }
-        self
+impl Default for Request {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-}
\ No newline at end of file
+#[cfg(test)]
+mod test {
+    use super::Request;
+
+    #[test]
+    fn multipart_body_is_closed() {
+        let request = Request::new()
+            .multipart()
+            .add_data("name", "value")
+            .add_file("file", "a.txt", b"bytes".to_vec());
+
+        let body = String::from_utf8(request.get_body().clone().unwrap()).unwrap();
+        assert_eq!(body, concat!(
+            "--X_HTTPCLIENT_BOUNDARY\r\n",
+            "Content-Disposition: form-data; name=\"name\"\r\n\r\n",
+            "value\r\n",
+            "--X_HTTPCLIENT_BOUNDARY\r\n",
+            "Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n",
+            "Content-Type: application/octet-stream\r\n\r\n",
+            "bytes\r\n",
+            "--X_HTTPCLIENT_BOUNDARY--\r\n"
+        ));
+    }
+
+}
@@ -0,0 +1,215 @@
+use std::{collections::HashMap, net::{IpAddr, SocketAddr}, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use async_trait::async_trait;
+
+use crate::app::error::Error;
+
+/// How long resolved addresses are reused by default, as long as curl
+/// keeps them.
+const CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// Resolved addresses by host, with when they were resolved.
+type Cache = HashMap<String, (Instant, Vec<IpAddr>)>;
+
+/// Turns host names into addresses.
+#[async_trait]
+pub trait Resolve: Send + Sync {
+
+    async fn resolve(&self, host: &str) -> Result<Vec<IpAddr>, Error>;
+
+}
+
+/// The resolver of the operating system, through `getaddrinfo`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemResolver;
+
+#[async_trait]
+impl Resolve for SystemResolver {
+
+    async fn resolve(&self, host: &str) -> Result<Vec<IpAddr>, Error> {
+        let addresses = tokio::net::lookup_host((host, 0)).await
+            .map_err(|e| Error::ResolveError(format!("{}: {}", host, e)))?;
+        Ok(addresses.map(|address| address.ip()).collect())
+    }
+
+}
+
+/// Which address families to connect to, and in which order.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum IpPreference {
+    /// In the order the resolver returns them.
+    #[default]
+    Any,
+    PreferIpv4,
+    PreferIpv6,
+    Ipv4Only,
+    Ipv6Only
+}
+
+/// Resolves the hosts of a client: overrides first, then cached results,
+/// then a `Resolve`, the system resolver by default.
+///
+/// Cloning is cheap and clones share the cache.
+#[derive(Clone)]
+pub struct Resolver {
+    resolve: Arc<dyn Resolve>,
+    /// By host and port, `None` standing for any port.
+    overrides: HashMap<(String, Option<usize>), Vec<IpAddr>>,
+    cache_ttl: Duration,
+    cache: Arc<Mutex<Cache>>,
+    ip_preference: IpPreference
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Resolver {
+            resolve: Arc::new(SystemResolver),
+            overrides: HashMap::new(),
+            cache_ttl: CACHE_TTL,
+            cache: Arc::default(),
+            ip_preference: IpPreference::Any
+        }
+    }
+}
+
+impl std::fmt::Debug for Resolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Resolver")
+            .field("overrides", &self.overrides)
+            .field("cache_ttl", &self.cache_ttl)
+            .field("ip_preference", &self.ip_preference)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Resolver {
+
+    pub fn new() -> Resolver {
+        Resolver::default()
+    }
+
+    /// Resolves with `resolve` instead of the system resolver.
+    pub fn set_resolve(mut self, resolve: impl Resolve + 'static) -> Resolver {
+        self.resolve = Arc::new(resolve);
+        self
+    }
+
+    /// Connects to `addresses` for `host`, on `port` or any port when it is
+    /// `None`, like curl's `--resolve` or an entry in `/etc/hosts`.
+    pub fn add_override(mut self, host: &str, port: Option<usize>, addresses: Vec<IpAddr>) -> Resolver {
+        self.overrides.insert((host.to_lowercase(), port), addresses);
+        self
+    }
+
+    /// How long resolved addresses are reused. `Duration::ZERO` asks the
+    /// resolver every time.
+    pub fn set_cache_ttl(mut self, cache_ttl: Duration) -> Resolver {
+        self.cache_ttl = cache_ttl;
+        self
+    }
+
+    pub fn get_cache_ttl(&self) -> Duration {
+        self.cache_ttl
+    }
+
+    pub fn set_ip_preference(mut self, ip_preference: IpPreference) -> Resolver {
+        self.ip_preference = ip_preference;
+        self
+    }
+
+    pub fn get_ip_preference(&self) -> IpPreference {
+        self.ip_preference
+    }
+
+    /// The addresses to try for `host:port`, in order.
+    pub async fn resolve(&self, host: &str, port: usize) -> Result<Vec<SocketAddr>, Error> {
+        let port_number = u16::try_from(port).map_err(|_| Error::UrlParsingError)?;
+        let addresses = self.addresses(host, port).await?;
+        let mut addresses: Vec<IpAddr> = match self.ip_preference {
+            IpPreference::Ipv4Only => addresses.into_iter().filter(IpAddr::is_ipv4).collect(),
+            IpPreference::Ipv6Only => addresses.into_iter().filter(IpAddr::is_ipv6).collect(),
+            _ => addresses,
+        };
+        match self.ip_preference {
+            IpPreference::PreferIpv4 => addresses.sort_by_key(IpAddr::is_ipv6),
+            IpPreference::PreferIpv6 => addresses.sort_by_key(IpAddr::is_ipv4),
+            _ => {},
+        }
+
+        if addresses.is_empty() {
+            return Err(Error::ResolveError(format!("{} has no address to connect to", host)));
+        }
+        Ok(addresses.into_iter().map(|ip| SocketAddr::new(ip, port_number)).collect())
+    }
+
+    async fn addresses(&self, host: &str, port: usize) -> Result<Vec<IpAddr>, Error> {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+        let host = host.to_lowercase();
+        let overridden = self.overrides.get(&(host.clone(), Some(port)))
+            .or_else(|| self.overrides.get(&(host.clone(), None)));
+        if let Some(addresses) = overridden {
+            return Ok(addresses.clone());
+        }
+
+        if let Some((resolved_at, addresses)) = self.cache.lock().unwrap().get(&host) {
+            if resolved_at.elapsed() < self.cache_ttl {
+                return Ok(addresses.clone());
+            }
+        }
+        let addresses = self.resolve.resolve(&host).await?;
+        if !self.cache_ttl.is_zero() {
+            self.cache.lock().unwrap().insert(host, (Instant::now(), addresses.clone()));
+        }
+        Ok(addresses)
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::IpAddr, sync::{atomic::{AtomicUsize, Ordering}, Arc}, time::Duration};
+
+    use async_trait::async_trait;
+
+    use crate::app::error::Error;
+    use super::{IpPreference, Resolve, Resolver};
+
+    struct Counting(Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl Resolve for Counting {
+        async fn resolve(&self, _host: &str) -> Result<Vec<IpAddr>, Error> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(vec!["2001:db8::1".parse().unwrap(), "192.0.2.1".parse().unwrap()])
+        }
+    }
+
+    #[tokio::test]
+    async fn overrides_cache_and_preference() {
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let resolver = Resolver::new()
+            .set_resolve(Counting(lookups.clone()))
+            .add_override("staging.example.com", Some(443), vec!["10.0.0.2".parse().unwrap()]);
+        let ips = |addresses: Vec<std::net::SocketAddr>| addresses.iter().map(|address| address.ip().to_string()).collect::<Vec<_>>();
+
+        assert_eq!(ips(resolver.resolve("Staging.example.com", 443).await.unwrap()), ["10.0.0.2"]);
+        assert_eq!(ips(resolver.resolve("staging.example.com", 80).await.unwrap()), ["2001:db8::1", "192.0.2.1"]);
+        assert_eq!(resolver.resolve("example.com", 8080).await.unwrap()[0].port(), 8080);
+        assert_eq!(lookups.load(Ordering::Relaxed), 2);
+        assert_eq!(ips(resolver.resolve("127.0.0.1", 80).await.unwrap()), ["127.0.0.1"]);
+
+        let preferring = resolver.clone().set_ip_preference(IpPreference::PreferIpv4);
+        assert_eq!(ips(preferring.resolve("example.com", 80).await.unwrap()), ["192.0.2.1", "2001:db8::1"]);
+        let only = resolver.clone().set_ip_preference(IpPreference::Ipv6Only);
+        assert_eq!(ips(only.resolve("example.com", 80).await.unwrap()), ["2001:db8::1"]);
+        assert_eq!(lookups.load(Ordering::Relaxed), 2);
+
+        let uncached = resolver.set_cache_ttl(Duration::ZERO);
+        uncached.resolve("example.com", 80).await.unwrap();
+        assert_eq!(lookups.load(Ordering::Relaxed), 3);
+        assert!(uncached.set_ip_preference(IpPreference::Ipv4Only).resolve("::1", 80).await.is_err());
+    }
+
+}
@@ -1,66 +1,74 @@
-use std::{collections::HashMap, ops::Range};
-use tokio::{io::AsyncReadExt, net::TcpStream};
+use std::{net::SocketAddr, ops::Range};
+use tokio::io::AsyncReadExt;
 use tokio::io::{AsyncRead, AsyncWrite};
 
 use crate::app::error::Error;
+use super::tls::TlsInfo;
 
+/// The status line and headers a server answered with, and the body once
+/// it is read.
 #[derive(Debug)]
 pub struct Response {
     pub version: String,
     pub status_code: usize,
     pub status_name: String,
-    pub headers: HashMap<String, String>,
+    /// Every header line in the order received, so repeated headers like
+    /// `Set-Cookie` are all kept.
+    pub headers: Vec<(String, String)>,
     pub body: Option<Vec<u8>>,
-    pub range: Option<Range<usize>>
+    pub range: Option<Range<usize>>,
+    /// The address connected to, which is the proxy's when there is one.
+    pub remote_addr: Option<SocketAddr>,
+    /// What the TLS handshake settled on, for `https` URLs.
+    pub tls: Option<TlsInfo>
 }
 
 impl Response {
 
     pub async fn new<T>(stream: &mut T) -> Result<Response, Error>
             where T: AsyncRead, T: AsyncWrite, T: Unpin {
+        let mut response = Self::read_head(stream).await?;
+        response.read_body(stream).await?;
+        Ok(response)
+    }
+
+    /// Reads the status line and headers, leaving the body in `stream` so it
+    /// can be consumed incrementally.
+    pub async fn read_head<T>(stream: &mut T) -> Result<Response, Error>
+            where T: AsyncRead, T: Unpin {
         let mut buff: Vec<u8> = vec![];
-        let mut headers: HashMap<String, String> = HashMap::new();
-        let mut body: Option<Vec<u8>> = None;
+        let mut headers: Vec<(String, String)> = vec![];
         let mut response_info = String::new();
-        let mut is_header_section = false;
 
-        loop {
-            match stream.read_u8().await {
-                Ok(byte) => {
-                    buff.push(byte);
-
-                    if byte as char != '\n' {
-                        continue;
-                    }
-
-                    if response_info.is_empty() {
-                        response_info = String::from_utf8(buff[..buff.len() - 2].to_vec())?;
-                        buff.clear();
-                        is_header_section = true;
-                        continue;
-                    }
-
-                    if is_header_section {
-                        if buff.len() == 2 && buff[0] as char == '\r' {
-                            is_header_section = false;
-                            buff.clear();
-                            continue;
-                        }
-
-                        let header_line = String::from_utf8(buff[..buff.len() - 2].to_vec())?;
-                        buff.clear();
-
-                        match header_line.split_once(":") {
-                            Some(v) => headers.insert(v.0.to_string(), v.1.to_string()),
-                            None => return Err(Error::HeaderParsingError),
-                        };
-                    }
-                },
-                Err(_) => break,
+        while let Ok(byte) = stream.read_u8().await {
+            buff.push(byte);
+
+            if byte as char != '\n' {
+                continue;
+            }
+
+            let line = head_line(&buff)?;
+            buff.clear();
+
+            if response_info.is_empty() {
+                if line.is_empty() {
+                    return Err(Error::HeaderParsingError);
+                }
+                response_info = line;
+                continue;
             }
+
+            if line.is_empty() {
+                break;
+            }
+
+            match line.split_once(":") {
+                Some(v) => headers.push((v.0.to_string(), v.1.to_string())),
+                None => return Err(Error::HeaderParsingError),
+            };
         }
 
-        let mut response_info_split = response_info.split(" ");
+        let mut response_info_split = response_info.splitn(3, " ");
 
         let version = match response_info_split.next() {
             Some(v) => v.to_string(),
@@ -68,7 +76,7 @@ impl Response {
         };
 
         let status_code = match response_info_split.next() {
-            Some(v) => v.parse().unwrap(),
+            Some(v) => v.parse().map_err(|_| Error::HeaderParsingError)?,
             None => return Err(Error::HeaderParsingError),
         };
 
@@ -77,13 +85,286 @@ impl Response {
             None => return Err(Error::HeaderParsingError),
         };
 
-        if buff.len() > 0 {
-            body = Some(buff);
-        }
-
         Ok(
-            Response { version, status_code, status_name, headers, body, range: None }
+            Response { version, status_code, status_name, headers, body: None, range: None, remote_addr: None, tls: None }
         )
     }
 
-}
\ No newline at end of file
+    /// Reads the rest of `stream` into `body`. The connection is closed by the
+    /// server after the response, so the body ends with the stream.
+    pub async fn read_body<T>(&mut self, stream: &mut T) -> Result<(), Error>
+            where T: AsyncRead, T: Unpin {
+        let mut body = vec![];
+        let mut chunk = [0u8; 16 * 1024];
+        let mut reader = self.body_reader(stream);
+
+        loop {
+            let read = reader.read(&mut chunk).await?;
+            if read == 0 {
+                break;
+            }
+            body.extend_from_slice(&chunk[..read]);
+        }
+
+        if !body.is_empty() {
+            self.body = Some(body);
+        }
+        Ok(())
+    }
+
+}
+
+impl Response {
+
+    /// Looks up a header case-insensitively and returns its trimmed value.
+    /// The first one counts when it was sent more than once.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim())
+    }
+
+    /// Every value of a header that may be sent more than once.
+    pub fn header_all(&self, name: &str) -> Vec<&str> {
+        self.headers.iter()
+            .filter(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim())
+            .collect()
+    }
+
+    /// Reads the body left in `stream` after `read_head`.
+    pub fn body_reader<'a, T>(&self, stream: &'a mut T) -> Body<'a, T> {
+        let chunked = self.header("Transfer-Encoding")
+            .and_then(|v| v.rsplit(',').next())
+            .map(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+            .unwrap_or(false);
+
+        let (framing, remaining) = match self.content_length() {
+            _ if chunked => (Framing::Chunked, 0),
+            Some(length) => (Framing::Length, length),
+            None => (Framing::Close, 0),
+        };
+        // Informational, 204 and 304 responses never have a body.
+        let done = self.status_code < 200 || self.status_code == 204 || self.status_code == 304;
+        Body { stream, framing, remaining, done }
+    }
+
+    pub fn content_length(&self) -> Option<usize> {
+        self.header("Content-Length").and_then(|v| v.parse().ok())
+    }
+
+    pub fn accepts_ranges(&self) -> bool {
+        match self.header("Accept-Ranges") {
+            Some(v) => v.split(',').any(|unit| unit.trim().eq_ignore_ascii_case("bytes")),
+            None => false,
+        }
+    }
+
+    /// Parses `Content-Range: bytes <start>-<end>/<total>` into an inclusive
+    /// range and the complete length, if the server announced one.
+    pub fn content_range(&self) -> Option<(Range<usize>, Option<usize>)> {
+        let value = self.header("Content-Range")?;
+        let (unit, spec) = value.split_once(' ')?;
+        if !unit.eq_ignore_ascii_case("bytes") {
+            return None;
+        }
+
+        let (range, total) = spec.trim().split_once('/')?;
+        let (start, end) = range.split_once('-')?;
+        let range = start.trim().parse().ok()?..end.trim().parse().ok()?;
+        let total = match total.trim() {
+            "*" => None,
+            v => Some(v.parse().ok()?),
+        };
+
+        Some((range, total))
+    }
+
+}
+
+/// A line of the head without its line break. Lines ending in a bare LF
+/// are accepted too, as RFC 9112 allows.
+fn head_line(line: &[u8]) -> Result<String, Error> {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    Ok(String::from_utf8(line.to_vec())?)
+}
+
+/// How the end of a body is found.
+enum Framing {
+    Chunked,
+    Length,
+    /// The body ends when the server closes the connection.
+    Close
+}
+
+/// The body of a response read straight from the connection, with the
+/// chunked transfer coding removed when the server used it.
+pub struct Body<'a, T> {
+    stream: &'a mut T,
+    framing: Framing,
+    /// Bytes left in the current chunk, or of the whole body.
+    remaining: usize,
+    done: bool
+}
+
+impl<'a, T> Body<'a, T> where T: AsyncRead, T: Unpin {
+
+    /// Reads into `buffer` and returns how many bytes were read, 0 once the
+    /// body is over.
+    pub async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        if self.done || buffer.is_empty() {
+            return Ok(0);
+        }
+
+        match self.framing {
+            Framing::Chunked => {},
+            Framing::Length => {
+                if self.remaining == 0 {
+                    return Ok(0);
+                }
+                let length = usize::min(buffer.len(), self.remaining);
+                let read = self.stream.read(&mut buffer[..length]).await?;
+                if read == 0 {
+                    return Err(Error::IoError("the connection closed before the body was complete".to_string()));
+                }
+                self.remaining -= read;
+                return Ok(read);
+            },
+            Framing::Close => {
+                return match self.stream.read(buffer).await {
+                    // Many servers close TLS connections without notifying,
+                    // which is the end of the body all the same.
+                    Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(0),
+                    result => Ok(result?),
+                };
+            },
+        }
+
+        if self.remaining == 0 {
+            let line = self.read_line().await?;
+            let size = line.split(';').next().unwrap_or_default().trim();
+            self.remaining = usize::from_str_radix(size, 16).map_err(|_| Error::ChunkParsingError)?;
+
+            if self.remaining == 0 {
+                // Trailer fields are not used, skip them.
+                while !self.read_line().await?.is_empty() {}
+                self.done = true;
+                return Ok(0);
+            }
+        }
+
+        let length = usize::min(buffer.len(), self.remaining);
+        let read = self.stream.read(&mut buffer[..length]).await?;
+        if read == 0 {
+            return Err(Error::ChunkParsingError);
+        }
+
+        self.remaining -= read;
+        if self.remaining == 0 && !self.read_line().await?.is_empty() {
+            return Err(Error::ChunkParsingError);
+        }
+        Ok(read)
+    }
+
+    async fn read_line(&mut self) -> Result<String, Error> {
+        let mut line = vec![];
+        loop {
+            let byte = self.stream.read_u8().await?;
+            if byte == b'\n' {
+                break;
+            }
+            line.push(byte);
+        }
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        Ok(String::from_utf8(line)?)
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use crate::app::error::Error;
+    use super::Response;
+
+    fn response_with(headers: &[(&str, &str)]) -> Response {
+        let headers = headers.iter()
+            .map(|(k, v)| (k.to_string(), format!(" {}", v)))
+            .collect();
+        Response {
+            version: "HTTP/1.1".to_owned(),
+            status_code: 206,
+            status_name: "Partial".to_owned(),
+            headers,
+            body: None,
+            range: None,
+            remote_addr: None,
+            tls: None
+        }
+    }
+
+    #[test]
+    fn header_lookup_is_case_insensitive() {
+        let response = response_with(&[("content-length", "42")]);
+        assert_eq!(response.header("Content-Length"), Some("42"));
+        assert_eq!(response.content_length(), Some(42));
+    }
+
+    #[test]
+    fn accept_ranges_bytes() {
+        assert!(response_with(&[("Accept-Ranges", "bytes")]).accepts_ranges());
+        assert!(!response_with(&[("Accept-Ranges", "none")]).accepts_ranges());
+        assert!(!response_with(&[]).accepts_ranges());
+    }
+
+    #[tokio::test]
+    async fn chunked_body_is_decoded() {
+        let response = response_with(&[("Transfer-Encoding", "chunked")]);
+        let mut stream: &[u8] = b"5;ext=1\r\nhello\r\n7\r\n, world\r\n0\r\nExpires: never\r\n\r\n";
+
+        let mut body = vec![];
+        let mut chunk = [0u8; 4];
+        let mut reader = response.body_reader(&mut stream);
+        loop {
+            let read = reader.read(&mut chunk).await.unwrap();
+            if read == 0 {
+                break;
+            }
+            body.extend_from_slice(&chunk[..read]);
+        }
+        assert_eq!(body, b"hello, world");
+    }
+
+    #[tokio::test]
+    async fn heads_with_bare_line_feeds() {
+        let mut stream: &[u8] = b"HTTP/1.1 200 OK\nContent-Length: 0\n\n";
+        let response = Response::read_head(&mut stream).await.unwrap();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.content_length(), Some(0));
+
+        let mut stream: &[u8] = b"\nHTTP/1.1 200 OK\r\n\r\n";
+        assert!(matches!(Response::read_head(&mut stream).await, Err(Error::HeaderParsingError)));
+    }
+
+    #[tokio::test]
+    async fn early_close_is_an_error() {
+        let mut response = response_with(&[("Content-Length", "10")]);
+        let mut stream: &[u8] = b"hell";
+        assert!(response.read_body(&mut stream).await.is_err());
+    }
+
+    #[test]
+    fn content_range_parsing() {
+        let response = response_with(&[("Content-Range", "bytes 0-499/1234")]);
+        assert_eq!(response.content_range(), Some((0..499, Some(1234))));
+
+        let response = response_with(&[("Content-Range", "bytes 500-999/*")]);
+        assert_eq!(response.content_range(), Some((500..999, None)));
+
+        let response = response_with(&[("Content-Range", "items 0-1/2")]);
+        assert_eq!(response.content_range(), None);
+    }
+
+}
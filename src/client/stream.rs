@@ -0,0 +1,62 @@
+use std::{io, net::SocketAddr, pin::Pin, task::{Context, Poll}};
+
+use tokio::{io::{AsyncRead, AsyncWrite, ReadBuf}, net::TcpStream};
+use tokio_rustls::client::TlsStream;
+
+use super::tls::TlsInfo;
+
+/// The connection to a server: plain TCP for `http` URLs, TLS for `https`.
+pub enum HttpStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>)
+}
+
+impl HttpStream {
+
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        match self {
+            HttpStream::Plain(stream) => stream.peer_addr().ok(),
+            HttpStream::Tls(stream) => stream.get_ref().0.peer_addr().ok(),
+        }
+    }
+
+    pub fn tls_info(&self) -> Option<TlsInfo> {
+        match self {
+            HttpStream::Plain(_) => None,
+            HttpStream::Tls(stream) => Some(TlsInfo::from_connection(stream.get_ref().1)),
+        }
+    }
+
+}
+
+impl AsyncRead for HttpStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            HttpStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            HttpStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for HttpStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            HttpStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            HttpStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            HttpStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            HttpStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            HttpStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            HttpStream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::{sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+/// Longest single sleep, so a changed rate takes effect quickly.
+static MAX_WAIT: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+struct Bucket {
+    rate: Option<usize>,
+    tokens: f64,
+    last_refill: Instant
+}
+
+impl Bucket {
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        if let Some(rate) = self.rate {
+            let elapsed = (now - self.last_refill).as_secs_f64();
+            // At most one second worth of bytes is saved up for bursts.
+            self.tokens = f64::min(rate as f64, self.tokens + elapsed * rate as f64);
+        }
+        self.last_refill = now;
+    }
+
+}
+
+/// A token bucket limiting throughput in bytes per second.
+///
+/// Clones share the same bucket, so one limiter handed to several downloads
+/// (or set on a `Client`) caps all of them together. The rate can be changed
+/// at any time with `set_rate`.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<Bucket>>
+}
+
+impl RateLimiter {
+
+    pub fn new(bytes_per_second: usize) -> RateLimiter {
+        Self::with_rate(Some(bytes_per_second))
+    }
+
+    pub fn unlimited() -> RateLimiter {
+        Self::with_rate(None)
+    }
+
+    fn with_rate(rate: Option<usize>) -> RateLimiter {
+        let bucket = Bucket { rate: rate.map(|r| r.max(1)), tokens: 0.0, last_refill: Instant::now() };
+        RateLimiter { bucket: Arc::new(Mutex::new(bucket)) }
+    }
+
+    /// Changes the rate; `None` removes the limit.
+    pub fn set_rate(&self, bytes_per_second: Option<usize>) {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.refill();
+        bucket.rate = bytes_per_second.map(|r| r.max(1));
+        if bucket.rate.is_none() {
+            bucket.tokens = 0.0;
+        }
+    }
+
+    pub fn get_rate(&self) -> Option<usize> {
+        self.bucket.lock().unwrap().rate
+    }
+
+    /// Takes `bytes` from the bucket, waiting until the debt they cause is
+    /// paid back. Chunks bigger than the bucket are allowed, they just wait
+    /// longer.
+    pub async fn acquire(&self, bytes: usize) {
+        {
+            let mut bucket = self.bucket.lock().unwrap();
+            if bucket.rate.is_none() {
+                return;
+            }
+            bucket.refill();
+            bucket.tokens -= bytes as f64;
+        }
+
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap();
+                bucket.refill();
+                match bucket.rate {
+                    Some(rate) if bucket.tokens < 0.0 => Duration::from_secs_f64(-bucket.tokens / rate as f64),
+                    _ => return,
+                }
+            };
+            tokio::time::sleep(wait.min(MAX_WAIT)).await;
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::RateLimiter;
+
+    #[tokio::test]
+    async fn unlimited_does_not_wait() {
+        let limiter = RateLimiter::unlimited();
+        let started = Instant::now();
+        limiter.acquire(100_000_000).await;
+        assert!(started.elapsed() < Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn limits_throughput() {
+        let limiter = RateLimiter::new(10_000);
+        let started = Instant::now();
+        for _ in 0..4 {
+            limiter.acquire(1_000).await;
+        }
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(350), "{:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn rate_can_be_lifted() {
+        let limiter = RateLimiter::new(1);
+        let waiting = limiter.clone();
+        let handle = tokio::spawn(async move { waiting.acquire(1_000).await });
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        limiter.set_rate(None);
+        tokio::time::timeout(Duration::from_secs(1), handle).await.unwrap().unwrap();
+    }
+
+}
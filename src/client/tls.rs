@@ -0,0 +1,499 @@
+use std::{path::Path, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex}, time::SystemTime};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::digest::{digest, SHA256};
+use tokio_rustls::rustls::{self, Certificate, ClientConfig, ClientConnection, OwnedTrustAnchor, PrivateKey, ProtocolVersion, RootCertStore, ServerName};
+use tokio_rustls::rustls::client::{ClientSessionMemoryCache, NoClientSessionStorage, ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use tokio_rustls::TlsConnector;
+
+use crate::app::error::Error;
+
+/// Where Linux distributions and the BSDs keep the bundle of trusted CA
+/// certificates, in the order `openssl` looks for them.
+static NATIVE_BUNDLES: [&str; 5] = [
+    "/etc/ssl/certs/ca-certificates.crt",
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/etc/ssl/ca-bundle.pem",
+    "/etc/pki/ca-trust/extracted/pem/tls-ca-bundle.pem",
+    "/etc/ssl/cert.pem",
+];
+
+/// How `https` connections verify servers and authenticate to them.
+///
+/// The default trusts the Mozilla roots bundled with the crate and sends no
+/// client certificate.
+#[derive(Clone)]
+pub struct TlsConfig {
+    webpki_roots: bool,
+    ca_certificates: Vec<Certificate>,
+    client_certificate: Option<(Vec<Certificate>, PrivateKey)>,
+    pins: Vec<Pin>,
+    accept_invalid_certs: bool,
+    session_cache_size: usize
+}
+
+/// Sessions kept for resumption by default, as many as rustls keeps.
+const SESSION_CACHE_SIZE: usize = 256;
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {
+            webpki_roots: true,
+            ca_certificates: vec![],
+            client_certificate: None,
+            pins: vec![],
+            accept_invalid_certs: false,
+            session_cache_size: SESSION_CACHE_SIZE
+        }
+    }
+}
+
+impl std::fmt::Debug for TlsConfig {
+    // Keeps the private key out of logs.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsConfig")
+            .field("webpki_roots", &self.webpki_roots)
+            .field("ca_certificates", &self.ca_certificates.len())
+            .field("client_certificate", &self.client_certificate.is_some())
+            .field("pins", &self.pins)
+            .field("accept_invalid_certs", &self.accept_invalid_certs)
+            .field("session_cache_size", &self.session_cache_size)
+            .finish()
+    }
+}
+
+impl TlsConfig {
+
+    pub fn new() -> TlsConfig {
+        TlsConfig::default()
+    }
+
+    /// Whether to trust the bundled Mozilla roots. Turning them off leaves
+    /// only the CA certificates added here, e.g. a private CA.
+    pub fn set_webpki_roots(mut self, webpki_roots: bool) -> TlsConfig {
+        self.webpki_roots = webpki_roots;
+        self
+    }
+
+    /// Trusts the CA certificates of a PEM bundle.
+    pub fn add_ca_pem(mut self, pem: &str) -> Result<TlsConfig, Error> {
+        let certificates = certificates(pem);
+        if certificates.is_empty() {
+            return Err(Error::TlsError("there is no certificate in the CA bundle".to_string()));
+        }
+        for certificate in &certificates {
+            RootCertStore::empty().add(certificate)
+                .map_err(|e| Error::TlsError(format!("a CA certificate is invalid: {}", e)))?;
+        }
+        self.ca_certificates.extend(certificates);
+        Ok(self)
+    }
+
+    pub async fn add_ca_file(self, path: &Path) -> Result<TlsConfig, Error> {
+        self.add_ca_pem(&tokio::fs::read_to_string(path).await?)
+    }
+
+    /// Trusts the CA certificates of the operating system: the bundle in
+    /// `SSL_CERT_FILE`, or the first one found where Linux distributions
+    /// and the BSDs install it. Certificates that do not parse are skipped.
+    pub async fn add_native_roots(mut self) -> Result<TlsConfig, Error> {
+        let mut paths: Vec<String> = std::env::var("SSL_CERT_FILE").into_iter().collect();
+        paths.extend(NATIVE_BUNDLES.iter().map(|path| path.to_string()));
+
+        for path in paths {
+            if let Ok(pem) = tokio::fs::read_to_string(&path).await {
+                self.ca_certificates.extend(certificates(&pem));
+                return Ok(self);
+            }
+        }
+        Err(Error::TlsError("there is no CA bundle of the system".to_string()))
+    }
+
+    /// Presents a certificate, followed by its intermediates, and signs with
+    /// its PKCS#8, PKCS#1 or SEC1 private key when the server asks for one.
+    pub fn set_client_certificate(mut self, certificate_pem: &str, key_pem: &str) -> Result<TlsConfig, Error> {
+        let chain = certificates(certificate_pem);
+        if chain.is_empty() {
+            return Err(Error::TlsError("there is no client certificate".to_string()));
+        }
+        let key = pem_blocks(key_pem).into_iter()
+            .find(|(label, _)| matches!(label.as_str(), "PRIVATE KEY" | "RSA PRIVATE KEY" | "EC PRIVATE KEY"))
+            .map(|(_, der)| PrivateKey(der))
+            .ok_or_else(|| Error::TlsError("there is no private key for the client certificate".to_string()))?;
+        rustls::sign::any_supported_type(&key)
+            .map_err(|_| Error::TlsError("the private key of the client certificate is not supported".to_string()))?;
+
+        self.client_certificate = Some((chain, key));
+        Ok(self)
+    }
+
+    /// Reads the client certificate and its key from PEM files, which may
+    /// be the same file.
+    pub async fn load_client_certificate(self, certificate: &Path, key: &Path) -> Result<TlsConfig, Error> {
+        let certificate_pem = tokio::fs::read_to_string(certificate).await?;
+        let key_pem = tokio::fs::read_to_string(key).await?;
+        self.set_client_certificate(&certificate_pem, &key_pem)
+    }
+
+    /// Accepts a server only when its certificate matches one of the pins,
+    /// on top of the usual verification.
+    pub fn add_pin(mut self, pin: Pin) -> TlsConfig {
+        self.pins.push(pin);
+        self
+    }
+
+    /// Accepts any certificate, expired, self-signed or for another host.
+    /// Only meant for testing: anyone in the middle can read the traffic.
+    /// Pins are still checked.
+    pub fn set_accept_invalid_certs(mut self, accept_invalid_certs: bool) -> TlsConfig {
+        self.accept_invalid_certs = accept_invalid_certs;
+        self
+    }
+
+    pub fn get_accept_invalid_certs(&self) -> bool {
+        self.accept_invalid_certs
+    }
+
+    /// How many sessions are kept to resume later handshakes with, which
+    /// skips the key exchange and certificate checks. 0 turns resumption
+    /// off.
+    pub fn set_session_cache_size(mut self, session_cache_size: usize) -> TlsConfig {
+        self.session_cache_size = session_cache_size;
+        self
+    }
+
+    pub fn get_session_cache_size(&self) -> usize {
+        self.session_cache_size
+    }
+
+    fn client_config(&self, counters: Arc<Counters>) -> Result<ClientConfig, Error> {
+        let mut roots = RootCertStore::empty();
+        if self.webpki_roots {
+            roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(
+                |ta| {
+                    OwnedTrustAnchor::from_subject_spki_name_constraints(
+                        ta.subject,
+                        ta.spki,
+                        ta.name_constraints,
+                    )
+                },
+            ));
+        }
+        for certificate in &self.ca_certificates {
+            // Native bundles may hold certificates webpki cannot parse.
+            let _ = roots.add(certificate);
+        }
+
+        let verifier = Verifier {
+            webpki: (!self.accept_invalid_certs).then(|| WebPkiVerifier::new(roots, None)),
+            pins: self.pins.clone(),
+            counters
+        };
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(verifier));
+
+        let mut config = match &self.client_certificate {
+            Some((chain, key)) => builder.with_single_cert(chain.clone(), key.clone())
+                .map_err(|e| Error::TlsError(e.to_string()))?,
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        config.session_storage = match self.session_cache_size {
+            0 => Arc::new(NoClientSessionStorage {}),
+            size => ClientSessionMemoryCache::new(size),
+        };
+        Ok(config)
+    }
+
+}
+
+/// How many TLS handshakes the connections of a client made.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TlsStats {
+    pub handshakes: u64,
+    /// Of `handshakes`, those that resumed an earlier session instead of
+    /// verifying the certificate again.
+    pub resumed: u64
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    handshakes: AtomicU64,
+    verified: AtomicU64
+}
+
+/// The TLS configuration a client builds once, so that all its connections
+/// share one session cache, and the handshakes they made.
+#[derive(Default)]
+pub(crate) struct SharedTls {
+    connector: Mutex<Option<TlsConnector>>,
+    counters: Arc<Counters>
+}
+
+impl std::fmt::Debug for SharedTls {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedTls").field("stats", &self.stats()).finish_non_exhaustive()
+    }
+}
+
+impl SharedTls {
+
+    pub(crate) fn connector(&self, config: &TlsConfig) -> Result<TlsConnector, Error> {
+        let mut connector = self.connector.lock().unwrap();
+        if let Some(connector) = connector.as_ref() {
+            return Ok(connector.clone());
+        }
+        let built = TlsConnector::from(Arc::new(config.client_config(self.counters.clone())?));
+        *connector = Some(built.clone());
+        Ok(built)
+    }
+
+    pub(crate) fn handshake_completed(&self) {
+        self.counters.handshakes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A resumed handshake is one that completed without the certificate
+    /// being verified.
+    pub(crate) fn stats(&self) -> TlsStats {
+        let handshakes = self.counters.handshakes.load(Ordering::Relaxed);
+        let verified = self.counters.verified.load(Ordering::Relaxed);
+        TlsStats { handshakes, resumed: handshakes.saturating_sub(verified) }
+    }
+
+}
+
+/// What a TLS handshake settled on.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TlsInfo {
+    /// e.g. `TLSv1.3`.
+    pub version: String,
+    /// The IANA name, e.g. `TLS13_AES_256_GCM_SHA384`.
+    pub cipher_suite: String,
+    pub alpn_protocol: Option<String>,
+    /// The DER encoded certificates the server sent, its own first.
+    pub peer_certificates: Vec<Vec<u8>>
+}
+
+impl TlsInfo {
+
+    pub(crate) fn from_connection(connection: &ClientConnection) -> TlsInfo {
+        let version = match connection.protocol_version() {
+            Some(ProtocolVersion::TLSv1_3) => "TLSv1.3".to_string(),
+            Some(ProtocolVersion::TLSv1_2) => "TLSv1.2".to_string(),
+            Some(version) => format!("{:?}", version),
+            None => String::new(),
+        };
+        TlsInfo {
+            version,
+            cipher_suite: connection.negotiated_cipher_suite()
+                .map(|suite| format!("{:?}", suite.suite()))
+                .unwrap_or_default(),
+            alpn_protocol: connection.alpn_protocol().map(|protocol| String::from_utf8_lossy(protocol).into_owned()),
+            peer_certificates: connection.peer_certificates()
+                .map(|certificates| certificates.iter().map(|certificate| certificate.0.clone()).collect())
+                .unwrap_or_default()
+        }
+    }
+
+    /// The pin of the server's public key in the `sha256//<base64>` form
+    /// `Pin::parse` takes.
+    pub fn public_key_pin(&self) -> Option<String> {
+        let spki = spki(self.peer_certificates.first()?)?;
+        Some(format!("sha256//{}", STANDARD.encode(digest(&SHA256, spki))))
+    }
+
+}
+
+/// A SHA-256 digest the certificate of a server has to match.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pin {
+    /// Of the DER encoded SubjectPublicKeyInfo, which survives the
+    /// certificate being renewed with the same key.
+    PublicKey(Vec<u8>),
+    /// Of the whole DER encoded certificate.
+    Certificate(Vec<u8>)
+}
+
+impl Pin {
+
+    /// Parses a public key pin in curl's `sha256//<base64>` form, or a
+    /// certificate fingerprint in hex, with or without colons, as
+    /// `openssl x509 -fingerprint -sha256` prints it.
+    pub fn parse(pin: &str) -> Result<Pin, Error> {
+        let invalid = || Error::TlsError(format!("the pin `{}` is neither sha256//<base64> nor a SHA-256 fingerprint", pin));
+
+        let pin = pin.trim();
+        let (pin, digest) = match pin.strip_prefix("sha256//") {
+            Some(encoded) => (Pin::PublicKey as fn(Vec<u8>) -> Pin, STANDARD.decode(encoded).map_err(|_| invalid())?),
+            None => {
+                let hex: String = pin.chars().filter(|c| *c != ':').collect();
+                let digest = (0..hex.len()).step_by(2)
+                    .map(|i| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+                    .collect::<Option<Vec<u8>>>()
+                    .ok_or_else(invalid)?;
+                (Pin::Certificate as fn(Vec<u8>) -> Pin, digest)
+            },
+        };
+        if digest.len() != SHA256.output_len {
+            return Err(invalid());
+        }
+        Ok(pin(digest))
+    }
+
+    fn matches(&self, certificate: &[u8]) -> bool {
+        match self {
+            Pin::PublicKey(expected) => spki(certificate)
+                .is_some_and(|spki| digest(&SHA256, spki).as_ref() == expected.as_slice()),
+            Pin::Certificate(expected) => digest(&SHA256, certificate).as_ref() == expected.as_slice(),
+        }
+    }
+
+}
+
+struct Verifier {
+    /// `None` when invalid certificates are accepted.
+    webpki: Option<WebPkiVerifier>,
+    pins: Vec<Pin>,
+    counters: Arc<Counters>
+}
+
+impl ServerCertVerifier for Verifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if let Some(webpki) = &self.webpki {
+            webpki.verify_server_cert(end_entity, intermediates, server_name, scts, ocsp_response, now)?;
+        }
+        if !self.pins.is_empty() && !self.pins.iter().any(|pin| pin.matches(&end_entity.0)) {
+            return Err(rustls::Error::General("the certificate of the server matches no pin".to_string()));
+        }
+        self.counters.verified.fetch_add(1, Ordering::Relaxed);
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// The `CERTIFICATE` blocks of a PEM bundle.
+fn certificates(pem: &str) -> Vec<Certificate> {
+    pem_blocks(pem).into_iter()
+        .filter(|(label, _)| label == "CERTIFICATE")
+        .map(|(_, der)| Certificate(der))
+        .collect()
+}
+
+/// The label and decoded contents of every block of a PEM file. Blocks that
+/// do not decode are skipped.
+fn pem_blocks(pem: &str) -> Vec<(String, Vec<u8>)> {
+    let mut blocks = vec![];
+    let mut lines = pem.lines().map(str::trim);
+
+    while let Some(line) = lines.next() {
+        let Some(label) = line.strip_prefix("-----BEGIN ").and_then(|rest| rest.strip_suffix("-----")) else {
+            continue;
+        };
+        let end = format!("-----END {}-----", label);
+        let encoded: String = lines.by_ref().take_while(|line| *line != end).collect();
+        if let Ok(der) = STANDARD.decode(encoded) {
+            blocks.push((label.to_string(), der));
+        }
+    }
+    blocks
+}
+
+/// The DER encoded SubjectPublicKeyInfo of a DER encoded X.509 certificate.
+fn spki(certificate: &[u8]) -> Option<&[u8]> {
+    let (certificate, _) = der_contents(certificate)?;
+    let (mut tbs, _) = der_contents(certificate)?;
+
+    // The optional version, then serial number, signature algorithm,
+    // issuer, validity and subject come before the key.
+    if tbs.first() == Some(&0xa0) {
+        tbs = der_contents(tbs)?.1;
+    }
+    for _ in 0..5 {
+        tbs = der_contents(tbs)?.1;
+    }
+    let rest = der_contents(tbs)?.1;
+    Some(&tbs[..tbs.len() - rest.len()])
+}
+
+/// The contents of the first DER element of `input`, and what follows it.
+fn der_contents(input: &[u8]) -> Option<(&[u8], &[u8])> {
+    let first = *input.get(1)?;
+    let (length, header) = if first < 0x80 {
+        (first as usize, 2)
+    }else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 {
+            return None;
+        }
+        let length = input.get(2..2 + count)?.iter().fold(0usize, |length, byte| length << 8 | *byte as usize);
+        (length, 2 + count)
+    };
+    let end = header.checked_add(length)?;
+    Some((input.get(header..end)?, &input[end..]))
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::Ordering;
+
+    use super::{pem_blocks, Pin, SharedTls, TlsConfig, TlsInfo, TlsStats};
+
+    /// A self-signed P-256 certificate for `localhost`.
+    const CERTIFICATE: &str = concat!(
+        "-----BEGIN CERTIFICATE-----\n",
+        "MIIBfzCCASWgAwIBAgIUEo0JsWBC13zsaAwOiPWhTBjhwqowCgYIKoZIzj0EAwIw\n",
+        "FDESMBAGA1UEAwwJbG9jYWxob3N0MCAXDTI2MTAxOTA2NDQ1NVoYDzIxMjYwOTI1\n",
+        "MDY0NDU1WjAUMRIwEAYDVQQDDAlsb2NhbGhvc3QwWTATBgcqhkjOPQIBBggqhkjO\n",
+        "PQMBBwNCAATg99sJHI1y2Yyh4OfBgQzEEBnAq1aKk6dLwyePZPOZeVsGD/SxAK3V\n",
+        "B27lLUP3bMVoWOtpGMisTR1sZhMargaYo1MwUTAdBgNVHQ4EFgQUk2l2in8cvuxy\n",
+        "YUD65QATq1TNBpMwHwYDVR0jBBgwFoAUk2l2in8cvuxyYUD65QATq1TNBpMwDwYD\n",
+        "VR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNIADBFAiBbKdu9p3RL/wgMcpKcZ9b9\n",
+        "7hr78g3LI2xQrtnJSeh5OwIhAN+s6dXM6sMitsM7EYa5HZwG/9k9axoKT7Y6xwgD\n",
+        "oWyB\n",
+        "-----END CERTIFICATE-----\n",
+    );
+
+    #[test]
+    fn pins_public_keys_and_certificates() {
+        let der = pem_blocks(CERTIFICATE).remove(0).1;
+
+        // As printed by `openssl x509 -pubkey | openssl pkey -pubin -outform der | openssl dgst -sha256 -binary | base64`.
+        assert!(Pin::parse("sha256//4cGoHusIjovcTPxIj+OIiT4qT0Ivx8BykkmG9w/qHnA=").unwrap().matches(&der));
+        assert!(Pin::parse("07:52:F1:C8:D8:B9:E9:0B:6C:8F:76:0A:05:5B:92:67:DF:3A:0C:AD:C6:7B:AC:AC:B6:92:F8:74:E7:7B:2A:E9").unwrap().matches(&der));
+        assert!(!Pin::parse(&"00".repeat(32)).unwrap().matches(&der));
+
+        let info = TlsInfo { version: "TLSv1.3".to_string(), cipher_suite: String::new(), alpn_protocol: None, peer_certificates: vec![der] };
+        assert_eq!(info.public_key_pin().as_deref(), Some("sha256//4cGoHusIjovcTPxIj+OIiT4qT0Ivx8BykkmG9w/qHnA="));
+
+        assert!(Pin::parse("sha256//AAAA").is_err());
+        assert!(Pin::parse("not a pin").is_err());
+    }
+
+    #[test]
+    fn loads_ca_certificates() {
+        assert!(TlsConfig::new().add_ca_pem(CERTIFICATE).is_ok());
+        assert!(TlsConfig::new().add_ca_pem("no certificates").is_err());
+        assert!(TlsConfig::new().set_client_certificate(CERTIFICATE, "").is_err());
+    }
+
+    #[test]
+    fn handshakes_without_verification_were_resumed() {
+        let shared = SharedTls::default();
+        shared.connector(&TlsConfig::new()).unwrap();
+        assert!(shared.connector.lock().unwrap().is_some());
+
+        shared.counters.verified.fetch_add(1, Ordering::Relaxed);
+        shared.handshake_completed();
+        shared.handshake_completed();
+        assert_eq!(shared.stats(), TlsStats { handshakes: 2, resumed: 1 });
+    }
+
+}
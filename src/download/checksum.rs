@@ -0,0 +1,346 @@
+use std::{collections::BTreeMap, fmt::Display};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use async_trait::async_trait;
+
+use crate::app::error::Error;
+use crate::client::response::Response;
+use super::sink::{RandomAccessSink, SequentialSink};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Algorithm {
+    Sha256,
+    Sha1,
+    Md5
+}
+
+impl Algorithm {
+
+    /// Maps the algorithm names used in `--checksum` style arguments and in
+    /// the `Digest` / `Repr-Digest` headers.
+    pub fn from_name(name: &str) -> Option<Algorithm> {
+        match name.to_ascii_lowercase().as_str() {
+            "sha256" | "sha-256" => Some(Algorithm::Sha256),
+            "sha1" | "sha-1" | "sha" => Some(Algorithm::Sha1),
+            "md5" => Some(Algorithm::Md5),
+            _ => None,
+        }
+    }
+
+}
+
+impl Display for Algorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Algorithm::Sha256 => write!(f, "sha256"),
+            Algorithm::Sha1 => write!(f, "sha1"),
+            Algorithm::Md5 => write!(f, "md5"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checksum {
+    pub algorithm: Algorithm,
+    pub digest: Vec<u8>
+}
+
+impl Checksum {
+
+    pub fn new(algorithm: Algorithm, hex_digest: &str) -> Result<Checksum, Error> {
+        let digest = from_hex(hex_digest).ok_or(Error::ChecksumParsingError)?;
+        Ok(Checksum { algorithm, digest })
+    }
+
+    /// Parses `<algorithm>:<hex digest>`, e.g. `sha256:e3b0c442...`.
+    pub fn parse(value: &str) -> Result<Checksum, Error> {
+        let (algorithm, digest) = value.split_once(':').ok_or(Error::ChecksumParsingError)?;
+        let algorithm = Algorithm::from_name(algorithm.trim()).ok_or(Error::ChecksumParsingError)?;
+        Self::new(algorithm, digest.trim())
+    }
+
+    /// Collects the digests a server announced for the whole file through
+    /// `Repr-Digest`, `Digest` or `Content-MD5`. Unknown algorithms are ignored.
+    pub fn from_response(response: &Response) -> Vec<Checksum> {
+        let mut checksums = vec![];
+
+        for value in response.header_all("Repr-Digest") {
+            for item in value.split(',') {
+                if let Some((name, digest)) = item.split_once('=') {
+                    push_base64(&mut checksums, name, digest.trim().trim_matches(':'));
+                }
+            }
+        }
+
+        for value in response.header_all("Digest") {
+            for item in value.split(',') {
+                if let Some((name, digest)) = item.split_once('=') {
+                    push_base64(&mut checksums, name, digest);
+                }
+            }
+        }
+
+        if let Some(value) = response.header("Content-MD5") {
+            push_base64(&mut checksums, "md5", value);
+        }
+
+        checksums
+    }
+
+}
+
+impl Display for Checksum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.algorithm, to_hex(&self.digest))
+    }
+}
+
+fn push_base64(checksums: &mut Vec<Checksum>, name: &str, digest: &str) {
+    let algorithm = match Algorithm::from_name(name.trim()) {
+        Some(v) => v,
+        None => return,
+    };
+    if let Ok(digest) = STANDARD.decode(digest.trim()) {
+        let checksum = Checksum { algorithm, digest };
+        if !checksums.contains(&checksum) {
+            checksums.push(checksum);
+        }
+    }
+}
+
+enum Hasher {
+    Sha256(Sha256),
+    Sha1(Sha1),
+    Md5(Md5)
+}
+
+impl Hasher {
+
+    fn new(algorithm: Algorithm) -> Hasher {
+        match algorithm {
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            Algorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+            Algorithm::Md5 => Hasher::Md5(Md5::new()),
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Hasher::Sha256(h) => h.update(bytes),
+            Hasher::Sha1(h) => h.update(bytes),
+            Hasher::Md5(h) => h.update(bytes),
+        }
+    }
+
+    fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Sha256(h) => h.finalize().to_vec(),
+            Hasher::Sha1(h) => h.finalize().to_vec(),
+            Hasher::Md5(h) => h.finalize().to_vec(),
+        }
+    }
+
+}
+
+/// Hashes a download while it is written.
+///
+/// Bytes must be hashed in file order, but segments arrive out of order. The
+/// verifier remembers which parts of the file are written and, as soon as the
+/// written prefix grows, reads the new bytes back from the file (still in the
+/// page cache) to feed the hashers.
+pub struct Verifier {
+    expected: Vec<Checksum>,
+    hashers: Vec<(Algorithm, Hasher)>,
+    position: usize,
+    written: BTreeMap<usize, usize>
+}
+
+impl Verifier {
+
+    pub fn new(expected: Vec<Checksum>) -> Verifier {
+        let mut hashers: Vec<(Algorithm, Hasher)> = vec![];
+        for checksum in &expected {
+            if !hashers.iter().any(|(algorithm, _)| *algorithm == checksum.algorithm) {
+                hashers.push((checksum.algorithm, Hasher::new(checksum.algorithm)));
+            }
+        }
+        Verifier { expected, hashers, position: 0, written: BTreeMap::new() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.expected.is_empty()
+    }
+
+    /// Feeds bytes that directly follow everything hashed so far.
+    pub fn update(&mut self, bytes: &[u8]) {
+        for (_, hasher) in self.hashers.iter_mut() {
+            hasher.update(bytes);
+        }
+        self.position += bytes.len();
+    }
+
+    /// Records that `offset..offset + length` is written to `sink` and hashes
+    /// whatever became contiguous with the already hashed prefix.
+    pub async fn written(&mut self, sink: &mut dyn RandomAccessSink, offset: usize, length: usize) -> Result<(), Error> {
+        if self.is_empty() || length == 0 {
+            return Ok(());
+        }
+
+        let end = offset + length;
+        let entry = self.written.entry(offset).or_insert(end);
+        *entry = usize::max(*entry, end);
+
+        let mut until = self.position;
+        while let Some((&start, &end)) = self.written.range(..=until).next_back() {
+            self.written.remove(&start);
+            until = usize::max(until, end);
+        }
+
+        if until > self.position {
+            self.catch_up(sink, until).await?;
+        }
+        Ok(())
+    }
+
+    async fn catch_up(&mut self, sink: &mut dyn RandomAccessSink, until: usize) -> Result<(), Error> {
+        let mut chunk = vec![0u8; 64 * 1024];
+        while self.position < until {
+            let length = usize::min(chunk.len(), until - self.position);
+            sink.read_at(self.position, &mut chunk[..length]).await?;
+            self.update(&chunk[..length]);
+        }
+        Ok(())
+    }
+
+    /// Hashes the rest of `sink` up to `length` and compares every expected
+    /// digest, failing with `Error::ChecksumMismatch` on the first difference.
+    pub async fn finish(mut self, sink: &mut dyn RandomAccessSink, length: usize) -> Result<(), Error> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        if self.position < length {
+            self.catch_up(sink, length).await?;
+        }
+        self.verify()
+    }
+
+    pub fn verify(self) -> Result<(), Error> {
+        let actual: Vec<(Algorithm, Vec<u8>)> = self.hashers.into_iter()
+            .map(|(algorithm, hasher)| (algorithm, hasher.finalize()))
+            .collect();
+
+        for checksum in self.expected {
+            let digest = actual.iter().find(|(a, _)| *a == checksum.algorithm).map(|(_, d)| d);
+            if let Some(digest) = digest {
+                if *digest != checksum.digest {
+                    return Err(Error::ChecksumMismatch {
+                        expected: checksum.to_string(),
+                        actual: Checksum { algorithm: checksum.algorithm, digest: digest.clone() }.to_string()
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+}
+
+/// A sink that only computes the digest of what is downloaded into it.
+pub struct DigestSink {
+    algorithm: Algorithm,
+    hasher: Hasher
+}
+
+impl DigestSink {
+
+    pub fn new(algorithm: Algorithm) -> DigestSink {
+        DigestSink { algorithm, hasher: Hasher::new(algorithm) }
+    }
+
+    pub fn checksum(self) -> Checksum {
+        Checksum { algorithm: self.algorithm, digest: self.hasher.finalize() }
+    }
+
+}
+
+#[async_trait]
+impl SequentialSink for DigestSink {
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.hasher.update(bytes);
+        Ok(())
+    }
+
+}
+
+fn from_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| value.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::client::response::Response;
+    use super::{Algorithm, Checksum, Verifier};
+
+    static EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn parse_checksum() {
+        let checksum = Checksum::parse(&format!("sha256:{}", EMPTY_SHA256)).unwrap();
+        assert_eq!(checksum.algorithm, Algorithm::Sha256);
+        assert_eq!(checksum.to_string(), format!("sha256:{}", EMPTY_SHA256));
+
+        assert!(Checksum::parse("crc32:00").is_err());
+        assert!(Checksum::parse("md5:xyz").is_err());
+    }
+
+    #[test]
+    fn checksums_from_headers() {
+        let headers = vec![
+            ("Repr-Digest".to_string(), " sha-256=:47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=:, sha-512=:AA==:".to_string()),
+            ("Content-MD5".to_string(), " 1B2M2Y8AsgTpgAmY7PhCfg==".to_string()),
+        ];
+        let response = Response {
+            version: "HTTP/1.1".to_owned(),
+            status_code: 200,
+            status_name: "OK".to_owned(),
+            headers,
+            body: None,
+            range: None,
+            remote_addr: None,
+            tls: None
+        };
+
+        let checksums = Checksum::from_response(&response);
+        assert_eq!(checksums.len(), 2);
+        assert_eq!(checksums[0], Checksum::parse(&format!("sha256:{}", EMPTY_SHA256)).unwrap());
+        assert_eq!(checksums[1], Checksum::parse("md5:d41d8cd98f00b204e9800998ecf8427e").unwrap());
+    }
+
+    #[test]
+    fn verify_digest() {
+        let expected = Checksum::parse("sha1:a9993e364706816aba3e25717850c26c9cd0d89d").unwrap();
+
+        let mut verifier = Verifier::new(vec![expected.clone()]);
+        verifier.update(b"abc");
+        assert!(verifier.verify().is_ok());
+
+        let mut verifier = Verifier::new(vec![expected]);
+        verifier.update(b"abd");
+        assert!(verifier.verify().is_err());
+    }
+
+}
@@ -0,0 +1,466 @@
+use std::{path::{Path, PathBuf}, sync::Arc};
+
+use tokio::fs::{File, OpenOptions};
+
+use crate::app::error::Error;
+use crate::client::{connection::{cancellable, Connection}, response::Response, method::Method, request::Request};
+#[cfg(feature = "checksum")]
+use super::checksum::Checksum;
+use super::{filename, options::{DownloadOptions, ExistingFile, PartialFile}, output::{Output, Sink}, progress::Progress, state::DownloadState};
+use super::sink::{RandomAccessSink, SequentialSink};
+use super::scheduler::{self, Source, CHUNK_SIZE};
+
+/// Redirects followed to reach the file, as many as curl follows.
+const MAX_REDIRECTS: usize = 50;
+
+impl Connection {
+
+    /// Downloads into `path`, or into a file named after the resource when
+    /// `path` is a directory, and returns the path of the finished file.
+    pub async fn download(&mut self, path: &Path) -> Result<PathBuf, Error> {
+        self.download_with(path, DownloadOptions::new()).await
+    }
+
+    pub async fn download_with(&mut self, path: &Path, options: DownloadOptions) -> Result<PathBuf, Error> {
+        // A second round is only needed when the remote file changes under a
+        // resumed download, so two attempts are enough.
+        let mut attempts_left = 2;
+
+        loop {
+            attempts_left -= 1;
+            match self.download_once(path, &options).await {
+                Err(Error::ResourceChanged) if attempts_left > 0 => continue,
+                result => return result,
+            }
+        }
+    }
+
+    /// Writes the download to `<target>.part` and renames it over the target
+    /// only once it is complete and verified, so the target path never holds
+    /// a partial file.
+    async fn download_once(&mut self, path: &Path, options: &DownloadOptions) -> Result<PathBuf, Error> {
+        let cancellation = options.get_cancellation();
+        let head_response = cancellable(cancellation, self.head()).await?;
+        let mut target = self.file_path(path, &head_response, options);
+
+        if target.exists() {
+            match options.get_existing() {
+                ExistingFile::Overwrite => {},
+                ExistingFile::Skip => return Ok(target),
+                ExistingFile::Rename => target = free_path(&target),
+            }
+        }
+
+        if let Some(progress) = options.get_progress() {
+            progress.file(&target);
+        }
+
+        let part_path = part_path(&target);
+        match self.download_part(&part_path, &head_response, options).await {
+            Ok(()) => {
+                tokio::fs::rename(&part_path, &target).await?;
+                sync_parent(&target).await;
+                Ok(target)
+            },
+            Err(e) => {
+                let corrupt = matches!(e, Error::ChecksumMismatch { .. });
+                let keep = match options.get_partial_file() {
+                    PartialFile::Resumable => options.get_resume() && !corrupt,
+                    PartialFile::Keep => true,
+                    PartialFile::Remove => false,
+                };
+                if !keep {
+                    remove_partial(&target).await?;
+                }else if corrupt {
+                    DownloadState::discard(&part_path).await?;
+                }
+                Err(e)
+            },
+        }
+    }
+
+    /// Downloads into `sink` and returns the length of the file. Nothing is
+    /// kept for resuming, everything else works as with a file.
+    pub async fn download_to(&mut self, sink: &mut dyn RandomAccessSink, options: DownloadOptions) -> Result<usize, Error> {
+        let head_response = cancellable(options.get_cancellation(), self.head()).await?;
+        let mut output = new_output(Sink::RandomAccess(sink), &options, &head_response);
+        let length = self.transfer(&mut output, &head_response, &options, None).await?;
+        output.finish(length).await?;
+        Ok(length)
+    }
+
+    /// Downloads into a sink that takes bytes only in order. Segments are
+    /// still fetched in parallel and put back in order in memory.
+    pub async fn download_stream_to(&mut self, sink: &mut dyn SequentialSink, options: DownloadOptions) -> Result<usize, Error> {
+        let head_response = cancellable(options.get_cancellation(), self.head()).await?;
+        let mut output = new_output(Sink::Sequential(sink), &options, &head_response);
+        let length = self.transfer(&mut output, &head_response, &options, None).await?;
+        output.finish(length).await?;
+        Ok(length)
+    }
+
+    async fn download_part(&self, file_path: &Path, head_response: &Response, options: &DownloadOptions) -> Result<(), Error> {
+        let content_length = head_response.content_length().unwrap_or(0);
+
+        let mut state = None;
+        if !is_segmented(head_response, options) {
+            DownloadState::discard(file_path).await?;
+        }else if options.get_resume() {
+            state = DownloadState::load(file_path).await?
+                .filter(|state| state.matches(content_length, head_response));
+        }
+
+        let mut file = open_file(file_path, state.is_none()).await?;
+
+        if is_segmented(head_response, options) && options.get_resume() && state.is_none() {
+            state = Some(DownloadState::create(file_path, content_length, head_response).await?);
+        }
+
+        let mut output = new_output(Sink::RandomAccess(&mut file), options, head_response);
+        let length = self.transfer(&mut output, head_response, options, state).await?;
+        output.finish(length).await
+    }
+
+    /// Fetches the file into `output`, in segments when the server supports
+    /// ranges, and returns its length.
+    ///
+    /// Cancellation is checked around every network operation rather than
+    /// around the whole transfer, so the segment workers are always joined.
+    async fn transfer(&self, output: &mut Output<'_>, head_response: &Response, options: &DownloadOptions, mut state: Option<DownloadState>) -> Result<usize, Error> {
+        let cancellation = options.get_cancellation();
+        let content_length = head_response.content_length().unwrap_or(0);
+        let progress = options.get_progress().cloned().unwrap_or_default();
+
+        if !is_segmented(head_response, options) {
+            if content_length > 0 {
+                output.set_len(content_length).await?;
+            }
+            return cancellable(cancellation, self.download_single(output, options, &progress)).await;
+        }
+
+        output.set_len(content_length).await?;
+
+        let sources = cancellable(cancellation, async { Ok(self.sources(head_response, content_length, options).await) }).await?;
+        let result = scheduler::download_segments(sources, output, content_length, options, &progress, state.as_mut()).await;
+
+        match result {
+            Ok(()) => {
+                if let Some(state) = state {
+                    state.remove().await?;
+                }
+                Ok(content_length)
+            },
+            Err(Error::RangeNotSupported) => {
+                if let Some(state) = state {
+                    state.remove().await?;
+                }
+                output.reset().await?;
+                cancellable(cancellation, self.download_single(output, options, &progress)).await
+            },
+            Err(Error::ResourceChanged) => {
+                if let Some(state) = state {
+                    state.remove().await?;
+                }
+                Err(Error::ResourceChanged)
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// This connection followed by every mirror that serves the same file,
+    /// i.e. supports ranges and agrees on the length and validators.
+    async fn sources(&self, head_response: &Response, content_length: usize, options: &DownloadOptions) -> Vec<Arc<Source>> {
+        let mut sources = vec![Arc::new(Source::new(self.clone(), head_response))];
+
+        for mirror in options.get_mirrors() {
+            let mut connection = match self.client.connect(mirror).await {
+                Ok(connection) => connection,
+                Err(_) => continue,
+            };
+            let mirror_response = match connection.head().await {
+                Ok(response) => response,
+                Err(_) => continue,
+            };
+
+            if is_same_file(head_response, &mirror_response, content_length) {
+                sources.push(Arc::new(Source::new(connection, &mirror_response)));
+            }
+        }
+
+        sources
+    }
+
+    /// Sends HEAD and follows redirects, so the connection ends up pointing
+    /// at the file itself. Any other answer than a success is an error.
+    async fn head(&mut self) -> Result<Response, Error> {
+        let head_request = Request::new().set_method(Method::HEAD);
+        let mut redirects = 0;
+
+        loop {
+            let head_response = self.request(head_request.clone()).await?;
+            if !matches!(head_response.status_code, 301 | 302 | 303 | 307 | 308) {
+                return check_status(head_response);
+            }
+
+            let location = head_response.header("Location").ok_or(Error::HeaderParsingError)?;
+            if redirects == MAX_REDIRECTS {
+                return Err(Error::TooManyRedirects);
+            }
+            redirects += 1;
+
+            let url = self.parsed_url.resolve(location);
+            self.handle_redirect(&url).await?;
+        }
+    }
+
+    fn file_path(&self, path: &Path, head_response: &Response, options: &DownloadOptions) -> PathBuf {
+        if !path.is_dir() {
+            return path.to_path_buf();
+        }
+
+        let file_name = filename::derive(
+            options.get_filename_sources(),
+            head_response.header("Content-Disposition"),
+            &self.parsed_url.path,
+            head_response.header("Content-Type")
+        );
+        path.join(file_name)
+    }
+
+    /// Streams the whole body into `output` and returns its length.
+    async fn download_single(&self, output: &mut Output<'_>, options: &DownloadOptions, progress: &Progress) -> Result<usize, Error> {
+        let (response, mut stream) = self.open(Request::new()).await?;
+        let response = check_status(response)?;
+        let mut body = response.body_reader(&mut stream);
+        progress.started(response.content_length());
+
+        let mut length = 0;
+        let mut chunk = vec![0u8; CHUNK_SIZE];
+        loop {
+            let read = body.read(&mut chunk).await?;
+            if read == 0 {
+                break;
+            }
+            output.append(&chunk[..read]).await?;
+            progress.received(None, read);
+            length += read;
+            scheduler::throttle(self, options.get_rate_limiter(), read).await;
+        }
+
+        progress.finished();
+        Ok(length)
+    }
+
+}
+
+/// Keeps error pages from being saved as the file.
+fn check_status(response: Response) -> Result<Response, Error> {
+    match response.status_code {
+        200..=299 => Ok(response),
+        status_code => Err(Error::HttpStatus(status_code)),
+    }
+}
+
+fn is_segmented(head_response: &Response, options: &DownloadOptions) -> bool {
+    head_response.accepts_ranges() && head_response.content_length().unwrap_or(0) > options.get_segment_size()
+}
+
+fn is_same_file(primary: &Response, mirror: &Response, content_length: usize) -> bool {
+    // A mirror without the validator the primary sends cannot prove that it
+    // serves the same file.
+    let agrees = |name: &str| match (primary.header(name), mirror.header(name)) {
+        (Some(a), Some(b)) => a == b,
+        (Some(_), None) => false,
+        (None, _) => true,
+    };
+
+    (200..300).contains(&mirror.status_code)
+        && mirror.accepts_ranges()
+        && mirror.content_length() == Some(content_length)
+        && agrees("ETag")
+        && agrees("Last-Modified")
+}
+
+async fn open_file(file_path: &Path, truncate: bool) -> Result<File, Error> {
+    let file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .write(true)
+        .truncate(truncate)
+        .open(file_path)
+        .await?;
+    Ok(file)
+}
+
+/// Removes what an unfinished download into `target` left behind.
+pub(crate) async fn remove_partial(target: &Path) -> Result<(), Error> {
+    let part_path = part_path(target);
+    DownloadState::discard(&part_path).await?;
+    let _ = tokio::fs::remove_file(&part_path).await;
+    Ok(())
+}
+
+fn part_path(target: &Path) -> PathBuf {
+    let mut name = target.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    target.with_file_name(name)
+}
+
+/// Finds the first `name (n).ext` next to `target` that does not exist yet.
+fn free_path(target: &Path) -> PathBuf {
+    let stem = target.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    let extension = target.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+
+    let mut n = 1;
+    loop {
+        let candidate = target.with_file_name(format!("{} ({}){}", stem, n, extension));
+        if !candidate.exists() && !part_path(&candidate).exists() {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+/// Makes the rename durable. Failing here does not undo the download, so
+/// errors are ignored.
+async fn sync_parent(target: &Path) {
+    #[cfg(unix)]
+    if let Some(parent) = target.parent().filter(|p| !p.as_os_str().is_empty()) {
+        if let Ok(dir) = File::open(parent).await {
+            let _ = dir.sync_all().await;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = target;
+}
+
+#[cfg(feature = "checksum")]
+fn new_output<'a>(sink: Sink<'a>, options: &DownloadOptions, head_response: &Response) -> Output<'a> {
+    let mut expected = options.get_checksums().clone();
+    expected.extend(Checksum::from_response(head_response));
+    Output::new(sink, expected)
+}
+
+#[cfg(not(feature = "checksum"))]
+fn new_output<'a>(sink: Sink<'a>, _options: &DownloadOptions, _head_response: &Response) -> Output<'a> {
+    Output::new(sink)
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener};
+
+    use crate::app::error::Error;
+    use crate::client::{connection::Connection, response::Response};
+    use super::{is_same_file, DownloadOptions};
+
+    /// Serves every connection with `respond(request head)`, then closes it.
+    async fn serve(respond: fn(&str) -> Vec<u8>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/file.bin", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut head = vec![];
+                while !head.ends_with(b"\r\n\r\n") {
+                    match socket.read_u8().await {
+                        Ok(byte) => head.push(byte),
+                        Err(_) => break,
+                    }
+                }
+                let _ = socket.write_all(&respond(&String::from_utf8_lossy(&head))).await;
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn early_close_fails_the_download() {
+        let url = serve(|request| match request.starts_with("HEAD") {
+            true => b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n".to_vec(),
+            false => b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhell".to_vec(),
+        }).await;
+
+        let mut sink: Vec<u8> = vec![];
+        let mut connection = Connection::new(&url).await.unwrap();
+        assert!(connection.download_to(&mut sink, DownloadOptions::new()).await.is_err());
+        assert!(sink.starts_with(b"hell"));
+    }
+
+    #[tokio::test]
+    async fn error_pages_are_not_downloaded() {
+        let url = serve(|_| b"HTTP/1.1 404 Not Found\r\nContent-Length: 9\r\n\r\nnot found".to_vec()).await;
+        let directory = std::env::temp_dir().join(format!("http_client_404_{}", std::process::id()));
+        tokio::fs::create_dir_all(&directory).await.unwrap();
+
+        let mut connection = Connection::new(&url).await.unwrap();
+        let result = connection.download(&directory.join("file.bin")).await;
+        let created = directory.join("file.bin").exists() || directory.join("file.bin.part").exists();
+        tokio::fs::remove_dir_all(&directory).await.unwrap();
+
+        assert!(matches!(result, Err(Error::HttpStatus(404))));
+        assert!(!created);
+
+        let url = serve(|request| match request.starts_with("HEAD") {
+            true => b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n".to_vec(),
+            false => b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 5\r\n\r\nerror".to_vec(),
+        }).await;
+        let mut sink: Vec<u8> = vec![];
+        let mut connection = Connection::new(&url).await.unwrap();
+        assert!(matches!(connection.download_to(&mut sink, DownloadOptions::new()).await, Err(Error::HttpStatus(500))));
+        assert!(!sink.starts_with(b"error"));
+    }
+
+    #[tokio::test]
+    async fn redirects_are_followed() {
+        let url = serve(|request| {
+            let path = request.split(' ').nth(1).unwrap_or_default();
+            match path {
+                "/file.bin" => b"HTTP/1.1 301 Moved Permanently\r\nLocation: moved/file.bin\r\nContent-Length: 0\r\n\r\n".to_vec(),
+                "/moved/file.bin" => b"HTTP/1.1 307 Temporary Redirect\r\nLocation: /final.bin\r\nContent-Length: 0\r\n\r\n".to_vec(),
+                "/final.bin" => b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello".to_vec(),
+                _ => b"HTTP/1.1 302 Found\r\nContent-Length: 0\r\n\r\n".to_vec(),
+            }
+        }).await;
+
+        let mut sink: Vec<u8> = vec![];
+        let mut connection = Connection::new(&url).await.unwrap();
+        connection.download_to(&mut sink, DownloadOptions::new()).await.unwrap();
+        assert_eq!(sink, b"hello");
+        assert_eq!(connection.parsed_url.path, "/final.bin");
+
+        let mut connection = Connection::new(&url.replace("file.bin", "nowhere")).await.unwrap();
+        assert!(matches!(connection.download_to(&mut sink, DownloadOptions::new()).await, Err(Error::HeaderParsingError)));
+    }
+
+    #[tokio::test]
+    async fn mirrors_need_the_validators_of_the_primary() {
+        let head = |validators: &str| {
+            let head = format!("HTTP/1.1 200 OK\r\nAccept-Ranges: bytes\r\nContent-Length: 10\r\n{}\r\n", validators);
+            async move { Response::read_head(&mut head.as_bytes()).await.unwrap() }
+        };
+        let primary = head("ETag: \"v1\"\r\n").await;
+
+        assert!(is_same_file(&primary, &head("ETag: \"v1\"\r\n").await, 10));
+        assert!(!is_same_file(&primary, &head("ETag: \"v2\"\r\n").await, 10));
+        assert!(!is_same_file(&primary, &head("").await, 10));
+        assert!(is_same_file(&head("").await, &primary, 10));
+    }
+
+    #[tokio::test]
+    async fn ignored_ranges_fall_back_to_one_stream() {
+        let url = serve(|request| {
+            let mut response = b"HTTP/1.1 200 OK\r\nAccept-Ranges: bytes\r\nETag: \"v1\"\r\nContent-Length: 200000\r\n\r\n".to_vec();
+            if !request.starts_with("HEAD") {
+                response.extend((0..200000).map(|i| i as u8));
+            }
+            response
+        }).await;
+
+        let mut sink: Vec<u8> = vec![];
+        let mut connection = Connection::new(&url).await.unwrap();
+        let options = DownloadOptions::new().set_segment_size(64 * 1024);
+        assert_eq!(connection.download_to(&mut sink, options).await.unwrap(), 200000);
+        assert!(sink.iter().enumerate().all(|(i, byte)| *byte == i as u8));
+    }
+
+}
@@ -0,0 +1,290 @@
+use rand::{thread_rng, Rng};
+
+/// Where the name of a downloaded file may come from, tried in the order
+/// given to `DownloadOptions::set_filename_sources`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilenameSource {
+    /// The `filename*` or `filename` parameter of `Content-Disposition`.
+    ContentDisposition,
+    /// The last segment of the URL path.
+    Url
+}
+
+/// Picks a safe file name for a download. The first source yielding a usable
+/// name wins; without one a random name is made up. A missing extension is
+/// inferred from `content_type`.
+pub fn derive(
+    sources: &[FilenameSource],
+    content_disposition: Option<&str>,
+    url_path: &str,
+    content_type: Option<&str>
+) -> String {
+    let name = sources.iter().find_map(|source| {
+        let name = match source {
+            FilenameSource::ContentDisposition => content_disposition.and_then(from_content_disposition),
+            FilenameSource::Url => from_url_path(url_path),
+        };
+        name.and_then(|name| sanitize(&name))
+    });
+
+    let mut name = name.unwrap_or_else(|| {
+        thread_rng()
+            .sample_iter(&rand::distributions::Alphanumeric)
+            .take(15)
+            .map(char::from)
+            .collect()
+    });
+
+    if !name.contains('.') {
+        if let Some(extension) = content_type.and_then(extension_for) {
+            name.push('.');
+            name.push_str(extension);
+        }
+    }
+
+    name
+}
+
+/// Extracts the file name from a `Content-Disposition` value as described in
+/// RFC 6266, preferring the RFC 5987 encoded `filename*` parameter.
+pub fn from_content_disposition(value: &str) -> Option<String> {
+    let mut filename = None;
+    let mut extended = None;
+
+    for param in split_params(value).into_iter().skip(1) {
+        let (key, value) = match param.split_once('=') {
+            Some((key, value)) => (key.trim().to_ascii_lowercase(), value.trim()),
+            None => continue,
+        };
+
+        match key.as_str() {
+            "filename" => filename = Some(unquote(value)),
+            "filename*" => extended = decode_ext_value(value),
+            _ => {},
+        }
+    }
+
+    extended.or(filename).filter(|name| !name.is_empty())
+}
+
+/// The percent-decoded last segment of a URL path, without its query.
+pub fn from_url_path(path: &str) -> Option<String> {
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    let segment = path.rsplit('/').next()?;
+    if segment.is_empty() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&percent_decode(segment)).to_string())
+}
+
+/// Makes a name from an untrusted source safe to join to a directory: only
+/// the last path component is kept, reserved and control characters are
+/// replaced, and names that are empty or special on some platform are
+/// refused or escaped.
+pub fn sanitize(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+
+    let name: String = name.chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    // Leading dots would hide the file or walk up the tree, trailing dots
+    // and spaces are dropped silently by Windows.
+    let mut name = name.trim_start_matches(['.', ' ']).trim_end_matches(['.', ' ']).to_string();
+    if name.is_empty() {
+        return None;
+    }
+
+    let stem = name.split('.').next().unwrap_or_default().to_ascii_uppercase();
+    let reserved = matches!(stem.as_str(), "CON" | "PRN" | "AUX" | "NUL")
+        || ((stem.starts_with("COM") || stem.starts_with("LPT"))
+            && stem.len() == 4
+            && stem.as_bytes()[3].is_ascii_digit());
+    if reserved {
+        name.insert(0, '_');
+    }
+
+    while name.len() > 255 {
+        name.pop();
+    }
+
+    Some(name)
+}
+
+pub fn extension_for(content_type: &str) -> Option<&'static str> {
+    let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    let extension = match mime.as_str() {
+        "text/plain" => "txt",
+        "text/html" => "html",
+        "text/css" => "css",
+        "text/csv" => "csv",
+        "text/javascript" | "application/javascript" => "js",
+        "application/json" => "json",
+        "application/xml" | "text/xml" => "xml",
+        "application/pdf" => "pdf",
+        "application/zip" => "zip",
+        "application/gzip" | "application/x-gzip" => "gz",
+        "application/x-tar" => "tar",
+        "application/x-xz" => "xz",
+        "application/x-bzip2" => "bz2",
+        "application/zstd" => "zst",
+        "application/x-7z-compressed" => "7z",
+        "application/vnd.rar" | "application/x-rar-compressed" => "rar",
+        "application/x-iso9660-image" => "iso",
+        "application/vnd.debian.binary-package" => "deb",
+        "application/x-rpm" => "rpm",
+        "application/wasm" => "wasm",
+        "image/png" => "png",
+        "image/jpeg" => "jpg",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/svg+xml" => "svg",
+        "audio/mpeg" => "mp3",
+        "audio/ogg" => "ogg",
+        "video/mp4" => "mp4",
+        "video/webm" => "webm",
+        _ => return None,
+    };
+    Some(extension)
+}
+
+/// Splits a header value on `;`, ignoring separators inside quoted strings.
+fn split_params(value: &str) -> Vec<String> {
+    let mut params = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for c in value.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                params.push(current.trim().to_string());
+                current.clear();
+                continue;
+            },
+            _ => {},
+        }
+        current.push(c);
+    }
+    params.push(current.trim().to_string());
+
+    params
+}
+
+fn unquote(value: &str) -> String {
+    let value = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(v) => v,
+        None => return value.to_string(),
+    };
+
+    let mut result = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(next) = chars.next() {
+                result.push(next);
+            }
+        }else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// Decodes an RFC 5987 `charset'language'value`.
+fn decode_ext_value(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let charset = parts.next()?.to_ascii_lowercase();
+    let _language = parts.next()?;
+    let bytes = percent_decode(parts.next()?);
+
+    match charset.as_str() {
+        "utf-8" => String::from_utf8(bytes).ok(),
+        "iso-8859-1" => Some(bytes.iter().map(|&b| b as char).collect()),
+        _ => None,
+    }
+}
+
+fn percent_decode(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = (char::from(bytes[i + 1]).to_digit(16), char::from(bytes[i + 2]).to_digit(16));
+            if let (Some(high), Some(low)) = hex {
+                result.push((high * 16 + low) as u8);
+                i += 3;
+                continue;
+            }
+        }
+        result.push(bytes[i]);
+        i += 1;
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::{derive, from_content_disposition, from_url_path, sanitize, FilenameSource};
+
+    static ORDER: [FilenameSource; 2] = [FilenameSource::ContentDisposition, FilenameSource::Url];
+
+    #[test]
+    fn content_disposition_plain_and_quoted() {
+        assert_eq!(from_content_disposition("attachment; filename=report.pdf"), Some("report.pdf".to_string()));
+        assert_eq!(
+            from_content_disposition("attachment; filename=\"annual \\\"report\\\"; 2022.pdf\""),
+            Some("annual \"report\"; 2022.pdf".to_string())
+        );
+        assert_eq!(from_content_disposition("inline"), None);
+    }
+
+    #[test]
+    fn content_disposition_prefers_extended_value() {
+        let value = "attachment; filename=\"EURO rates.txt\"; filename*=UTF-8''%e2%82%ac%20rates.txt";
+        assert_eq!(from_content_disposition(value), Some("€ rates.txt".to_string()));
+
+        let value = "attachment; filename*=iso-8859-1'en'%A3%20rates.txt";
+        assert_eq!(from_content_disposition(value), Some("£ rates.txt".to_string()));
+    }
+
+    #[test]
+    fn url_segments_are_decoded() {
+        assert_eq!(from_url_path("/files/my%20file.tar.gz?x=1"), Some("my file.tar.gz".to_string()));
+        assert_eq!(from_url_path("/files/"), None);
+        assert_eq!(from_url_path("/100%"), Some("100%".to_string()));
+    }
+
+    #[test]
+    fn traversal_is_removed() {
+        assert_eq!(sanitize("../../etc/passwd"), Some("passwd".to_string()));
+        assert_eq!(sanitize("/etc/passwd"), Some("passwd".to_string()));
+        assert_eq!(sanitize("..\\..\\boot.ini"), Some("boot.ini".to_string()));
+        assert_eq!(sanitize(".."), None);
+        assert_eq!(sanitize(".bashrc"), Some("bashrc".to_string()));
+        assert_eq!(sanitize("a<b>:c?.txt"), Some("a_b__c_.txt".to_string()));
+        assert_eq!(sanitize("con.txt"), Some("_con.txt".to_string()));
+    }
+
+    #[test]
+    fn derive_by_preference() {
+        let disposition = Some("attachment; filename=\"../evil.sh\"");
+        assert_eq!(derive(&ORDER, disposition, "/dl/file.bin", None), "evil.sh");
+        assert_eq!(derive(&[FilenameSource::Url], disposition, "/dl/file.bin", None), "file.bin");
+        assert_eq!(derive(&ORDER, None, "/dl/latest", Some("application/zip")), "latest.zip");
+
+        let random = derive(&ORDER, None, "/", Some("application/pdf; charset=binary"));
+        assert!(random.ends_with(".pdf") && random.len() == 19);
+    }
+
+}
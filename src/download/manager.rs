@@ -0,0 +1,510 @@
+use std::{collections::HashMap, path::{Path, PathBuf}, sync::{Arc, Mutex}};
+
+use tokio::sync::{Mutex as AsyncMutex, Notify};
+use tokio_util::sync::CancellationToken;
+
+use crate::app::error::Error;
+use crate::client::client::Client;
+use super::{engine, options::{DownloadOptions, PartialFile}, progress::Progress};
+
+static HEADER: &str = "http_client-queue 1";
+
+pub type ItemId = u64;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ItemStatus {
+    Queued,
+    Running,
+    Paused,
+    Completed(PathBuf),
+    Failed(String),
+    Cancelled
+}
+
+impl ItemStatus {
+
+    fn is_done(&self) -> bool {
+        matches!(self, ItemStatus::Completed(_) | ItemStatus::Failed(_) | ItemStatus::Cancelled)
+    }
+
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueueItem {
+    pub id: ItemId,
+    pub url: String,
+    pub destination: PathBuf,
+    /// Higher runs first; items of equal priority run in the order added.
+    pub priority: i32,
+    pub status: ItemStatus,
+    /// The file an unfinished download writes to, once its name is known.
+    pub file: Option<PathBuf>
+}
+
+struct Running {
+    cancellation: CancellationToken,
+    progress: Progress
+}
+
+struct Queue {
+    items: Vec<QueueItem>,
+    next_id: ItemId,
+    running: HashMap<ItemId, Running>
+}
+
+/// Runs many downloads, a limited number at a time.
+///
+/// Items are taken by priority, can be paused, resumed and cancelled one by
+/// one, and with `open` the queue is kept in a file so it survives restarts.
+/// Downloads always resume, so pausing keeps what is already on disk, while
+/// cancelling removes it.
+#[derive(Clone)]
+pub struct DownloadManager {
+    client: Client,
+    options: DownloadOptions,
+    concurrency: usize,
+    path: Option<PathBuf>,
+    queue: Arc<Mutex<Queue>>,
+    changed: Arc<Notify>,
+    saving: Arc<AsyncMutex<()>>
+}
+
+impl DownloadManager {
+
+    pub fn new(client: Client, concurrency: usize) -> DownloadManager {
+        DownloadManager {
+            client,
+            options: DownloadOptions::new(),
+            concurrency: concurrency.max(1),
+            path: None,
+            queue: Arc::new(Mutex::new(Queue { items: vec![], next_id: 1, running: HashMap::new() })),
+            changed: Arc::new(Notify::new()),
+            saving: Arc::new(AsyncMutex::new(()))
+        }
+    }
+
+    /// A manager persisting its queue to `path`. Items of an earlier run are
+    /// loaded and unfinished ones start again.
+    pub async fn open(client: Client, concurrency: usize, path: &Path) -> Result<DownloadManager, Error> {
+        let mut manager = Self::new(client, concurrency);
+        manager.path = Some(path.to_path_buf());
+
+        if path.exists() {
+            let content = tokio::fs::read_to_string(path).await?;
+            let items = parse(&content).ok_or(Error::QueueParsingError)?;
+            let mut queue = manager.queue.lock().unwrap();
+            queue.next_id = items.iter().map(|item| item.id + 1).max().unwrap_or(1);
+            queue.items = items;
+        }
+
+        manager.schedule();
+        Ok(manager)
+    }
+
+    /// Options used for every download. Resuming is always turned on and
+    /// partial files are kept until an item is cancelled.
+    pub fn set_options(mut self, options: DownloadOptions) -> DownloadManager {
+        self.options = options;
+        self
+    }
+
+    pub async fn enqueue(&self, url: &str, destination: &Path, priority: i32) -> Result<ItemId, Error> {
+        let id = {
+            let mut queue = self.queue.lock().unwrap();
+            let id = queue.next_id;
+            queue.next_id += 1;
+            queue.items.push(QueueItem {
+                id,
+                url: url.to_string(),
+                destination: destination.to_path_buf(),
+                priority,
+                status: ItemStatus::Queued,
+                file: None
+            });
+            id
+        };
+
+        self.changed().await?;
+        Ok(id)
+    }
+
+    pub async fn pause(&self, id: ItemId) -> Result<(), Error> {
+        self.stop(id, ItemStatus::Paused, |status| matches!(status, ItemStatus::Queued | ItemStatus::Running))?;
+        self.changed().await
+    }
+
+    pub async fn resume(&self, id: ItemId) -> Result<(), Error> {
+        self.set_status(id, ItemStatus::Queued, |status| *status == ItemStatus::Paused)?;
+        self.changed().await
+    }
+
+    pub async fn cancel(&self, id: ItemId) -> Result<(), Error> {
+        // A running download removes its partial file once it stops, the one
+        // of an item waiting to resume is removed here.
+        let partial = {
+            let queue = self.queue.lock().unwrap();
+            let item = queue.items.iter().find(|item| item.id == id).ok_or(Error::UnknownQueueItem)?;
+            let idle = matches!(item.status, ItemStatus::Queued | ItemStatus::Paused) && !queue.running.contains_key(&id);
+            item.file.clone().filter(|_| idle)
+        };
+
+        self.stop(id, ItemStatus::Cancelled, |status| !status.is_done())?;
+        if let Some(file) = partial {
+            engine::remove_partial(&file).await?;
+        }
+        self.changed().await
+    }
+
+    /// Forgets an item that is not running.
+    pub async fn remove(&self, id: ItemId) -> Result<(), Error> {
+        {
+            let mut queue = self.queue.lock().unwrap();
+            let item = queue.items.iter().find(|item| item.id == id).ok_or(Error::UnknownQueueItem)?;
+            if item.status == ItemStatus::Running {
+                return Err(Error::QueueItemRunning);
+            }
+            queue.items.retain(|item| item.id != id);
+        }
+        self.changed().await
+    }
+
+    pub fn items(&self) -> Vec<QueueItem> {
+        self.queue.lock().unwrap().items.clone()
+    }
+
+    pub fn item(&self, id: ItemId) -> Option<QueueItem> {
+        self.queue.lock().unwrap().items.iter().find(|item| item.id == id).cloned()
+    }
+
+    /// Progress of a running item.
+    pub fn progress(&self, id: ItemId) -> Option<Progress> {
+        self.queue.lock().unwrap().running.get(&id).map(|running| running.progress.clone())
+    }
+
+    /// Waits until nothing is queued or running anymore.
+    pub async fn wait(&self) {
+        loop {
+            let changed = self.changed.notified();
+            let busy = self.queue.lock().unwrap().items.iter()
+                .any(|item| matches!(item.status, ItemStatus::Queued | ItemStatus::Running));
+            if !busy {
+                return;
+            }
+            changed.await;
+        }
+    }
+
+}
+
+impl DownloadManager {
+
+    fn set_status<F: Fn(&ItemStatus) -> bool>(&self, id: ItemId, status: ItemStatus, allowed: F) -> Result<(), Error> {
+        let mut queue = self.queue.lock().unwrap();
+        let item = queue.items.iter_mut().find(|item| item.id == id).ok_or(Error::UnknownQueueItem)?;
+        if allowed(&item.status) {
+            item.status = status;
+        }
+        Ok(())
+    }
+
+    fn stop<F: Fn(&ItemStatus) -> bool>(&self, id: ItemId, status: ItemStatus, allowed: F) -> Result<(), Error> {
+        self.set_status(id, status, allowed)?;
+        let queue = self.queue.lock().unwrap();
+        let stopped = queue.items.iter().any(|item| item.id == id && item.status != ItemStatus::Running);
+        if stopped {
+            if let Some(running) = queue.running.get(&id) {
+                running.cancellation.cancel();
+            }
+        }
+        Ok(())
+    }
+
+    /// Waiters are woken only once the queue is saved, so it is on disk by
+    /// the time `wait` returns.
+    async fn changed(&self) -> Result<(), Error> {
+        self.schedule();
+        let result = self.save().await;
+        self.changed.notify_waiters();
+        result
+    }
+
+    /// Starts the most important queued items while there is room.
+    fn schedule(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        let Queue { items, running, .. } = &mut *queue;
+
+        while running.len() < self.concurrency {
+            // A paused item resumed before its download wound down waits for
+            // it to finish.
+            let next = items.iter_mut()
+                .filter(|item| item.status == ItemStatus::Queued && !running.contains_key(&item.id))
+                .min_by_key(|item| (-(item.priority as i64), item.id));
+            let item = match next {
+                Some(item) => item,
+                None => break,
+            };
+            item.status = ItemStatus::Running;
+
+            let id = item.id;
+            let progress = Progress::new();
+            let cancellation = CancellationToken::new();
+            let options = self.options.clone()
+                .set_resume(true)
+                .set_partial_file(PartialFile::Resumable)
+                .set_progress(progress.clone())
+                .set_cancellation(cancellation.clone());
+            tokio::spawn(self.clone().run(item.clone(), options));
+            running.insert(id, Running { cancellation, progress });
+        }
+    }
+
+    async fn run(self, item: QueueItem, options: DownloadOptions) {
+        let progress = options.get_progress().cloned().unwrap_or_default();
+        let result = async {
+            let mut connection = self.client.connect(&item.url).await?;
+            connection.download_with(&item.destination, options).await
+        }.await;
+
+        let cancelled_file = {
+            let mut queue = self.queue.lock().unwrap();
+            queue.running.remove(&item.id);
+            match queue.items.iter_mut().find(|entry| entry.id == item.id) {
+                Some(entry) => {
+                    if entry.status == ItemStatus::Running {
+                        entry.status = match result {
+                            Ok(path) => ItemStatus::Completed(path),
+                            Err(e) => ItemStatus::Failed(e.to_string()),
+                        };
+                    }
+                    let file = progress.snapshot().file.or(entry.file.take());
+                    match entry.status {
+                        ItemStatus::Queued | ItemStatus::Paused => {
+                            entry.file = file;
+                            None
+                        },
+                        ItemStatus::Cancelled => file,
+                        _ => None,
+                    }
+                },
+                None => None,
+            }
+        };
+
+        if let Some(file) = cancelled_file {
+            let _ = engine::remove_partial(&file).await;
+        }
+
+        // Nobody is left to report a failed save to, the next change saves
+        // the queue again.
+        let _ = self.changed().await;
+    }
+
+    async fn save(&self) -> Result<(), Error> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        // Saves run one at a time and each takes the queue as it is then, so
+        // an older copy never overwrites a newer one.
+        let _saving = self.saving.lock().await;
+        let content = format(&self.items());
+        let mut temporary = path.as_os_str().to_os_string();
+        temporary.push(".tmp");
+        tokio::fs::write(&temporary, content).await?;
+        tokio::fs::rename(&temporary, path).await?;
+        Ok(())
+    }
+
+}
+
+/// One tab separated line per item: id, priority, status, url, destination
+/// and the result of finished items or the partial file of unfinished ones.
+/// Fields are percent-encoded where they hold `%`, tabs or line breaks.
+fn format(items: &[QueueItem]) -> String {
+    let mut content = format!("{}\n", HEADER);
+    for item in items {
+        let file = item.file.as_ref().map(|file| file.to_string_lossy().to_string()).unwrap_or_default();
+        let (status, detail) = match &item.status {
+            // A running item starts over after a restart.
+            ItemStatus::Queued | ItemStatus::Running => ("queued", file),
+            ItemStatus::Paused => ("paused", file),
+            ItemStatus::Completed(path) => ("completed", path.to_string_lossy().to_string()),
+            ItemStatus::Failed(e) => ("failed", e.to_string()),
+            ItemStatus::Cancelled => ("cancelled", String::new()),
+        };
+        content.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\n",
+            item.id, item.priority, status, escape(&item.url), escape(&item.destination.to_string_lossy()), escape(&detail)
+        ));
+    }
+    content
+}
+
+fn parse(content: &str) -> Option<Vec<QueueItem>> {
+    let mut lines = content.lines();
+    if lines.next()? != HEADER {
+        return None;
+    }
+
+    let mut items = vec![];
+    for line in lines.filter(|line| !line.is_empty()) {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 6 {
+            return None;
+        }
+
+        let detail = unescape(fields[5])?;
+        let (status, file) = match fields[2] {
+            "queued" => (ItemStatus::Queued, Some(detail)),
+            "paused" => (ItemStatus::Paused, Some(detail)),
+            "completed" => (ItemStatus::Completed(PathBuf::from(detail)), None),
+            "failed" => (ItemStatus::Failed(detail), None),
+            "cancelled" => (ItemStatus::Cancelled, None),
+            _ => return None,
+        };
+
+        items.push(QueueItem {
+            id: fields[0].parse().ok()?,
+            url: unescape(fields[3])?,
+            destination: PathBuf::from(unescape(fields[4])?),
+            priority: fields[1].parse().ok()?,
+            status,
+            file: file.filter(|file| !file.is_empty()).map(PathBuf::from)
+        });
+    }
+
+    Some(items)
+}
+
+fn escape(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '%' | '\t' | '\n' | '\r' => escaped.push_str(&format!("%{:02X}", c as u8)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(field: &str) -> Option<String> {
+    let mut bytes = vec![];
+    let mut rest = field.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        }else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use tokio::net::TcpListener;
+
+    use crate::app::error::Error;
+    use crate::client::client::Client;
+    use super::{format, parse, DownloadManager, ItemStatus, QueueItem};
+
+    #[test]
+    fn queue_round_trip() {
+        let items = vec![
+            QueueItem {
+                id: 1,
+                url: "https://example.com/a%20b.iso".to_owned(),
+                destination: PathBuf::from("/tmp/my\tdownloads\n"),
+                priority: 5,
+                status: ItemStatus::Paused,
+                file: Some(PathBuf::from("/tmp/my\tdownloads\n/a b.iso"))
+            },
+            QueueItem {
+                id: 7,
+                url: "https://example.com/b.iso".to_owned(),
+                destination: PathBuf::from("/tmp"),
+                priority: -1,
+                status: ItemStatus::Failed("An error occurred on IO operation: reset\nby peer".to_owned()),
+                file: None
+            },
+            QueueItem {
+                id: 8,
+                url: "https://example.com/c.iso".to_owned(),
+                destination: PathBuf::from("/tmp"),
+                priority: 0,
+                status: ItemStatus::Completed(PathBuf::from("/tmp/c.iso")),
+                file: None
+            },
+        ];
+
+        let content = format(&items);
+        assert_eq!(content.lines().count(), 4);
+        assert_eq!(parse(&content).unwrap(), items);
+    }
+
+    #[test]
+    fn running_items_are_queued_again() {
+        let item = QueueItem {
+            id: 1,
+            url: "https://example.com/a.iso".to_owned(),
+            destination: PathBuf::from("/tmp"),
+            priority: 0,
+            status: ItemStatus::Running,
+            file: None
+        };
+
+        assert_eq!(parse(&format(&[item])).unwrap()[0].status, ItemStatus::Queued);
+    }
+
+    #[tokio::test]
+    async fn cancelling_a_paused_item_removes_its_partial_file() {
+        let directory = std::env::temp_dir().join(format!("http_client_queue_{}", std::process::id()));
+        tokio::fs::create_dir_all(&directory).await.unwrap();
+        let file = directory.join("a.iso");
+        let part = directory.join("a.iso.part");
+        let state = directory.join("a.iso.part.state");
+        tokio::fs::write(&part, b"partial").await.unwrap();
+        tokio::fs::write(&state, b"state").await.unwrap();
+        let queue = directory.join("queue");
+        let item = QueueItem {
+            id: 1,
+            url: "https://example.com/a.iso".to_owned(),
+            destination: directory.clone(),
+            priority: 0,
+            status: ItemStatus::Paused,
+            file: Some(file)
+        };
+        tokio::fs::write(&queue, format(&[item])).await.unwrap();
+
+        let manager = DownloadManager::open(Client::new(), 1, &queue).await.unwrap();
+        manager.cancel(1).await.unwrap();
+        let (part_exists, state_exists) = (part.exists(), state.exists());
+        tokio::fs::remove_dir_all(&directory).await.unwrap();
+
+        assert_eq!(manager.item(1).unwrap().status, ItemStatus::Cancelled);
+        assert!(!part_exists && !state_exists);
+    }
+
+    #[tokio::test]
+    async fn running_items_are_not_removed() {
+        // Connections are accepted by the system but never answered.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/a.iso", listener.local_addr().unwrap());
+
+        let manager = DownloadManager::new(Client::new(), 1);
+        let id = manager.enqueue(&url, &std::env::temp_dir(), 0).await.unwrap();
+        assert!(matches!(manager.remove(id).await, Err(Error::QueueItemRunning)));
+
+        manager.cancel(id).await.unwrap();
+        manager.wait().await;
+        manager.remove(id).await.unwrap();
+        assert!(manager.items().is_empty());
+    }
+
+}
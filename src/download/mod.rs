@@ -0,0 +1,15 @@
+#[cfg(feature = "checksum")]
+pub mod checksum;
+pub mod engine;
+pub mod filename;
+pub mod manager;
+pub mod options;
+mod output;
+pub mod progress;
+mod scheduler;
+pub mod sink;
+pub mod state;
+
+pub use manager::DownloadManager;
+pub use options::DownloadOptions;
+pub use progress::Progress;
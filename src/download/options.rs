@@ -0,0 +1,195 @@
+#[cfg(feature = "checksum")]
+use super::checksum::Checksum;
+use tokio_util::sync::CancellationToken;
+
+use crate::client::throttle::RateLimiter;
+use super::{filename::FilenameSource, progress::Progress};
+
+/// What to do when the target file of a download already exists.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExistingFile {
+    Overwrite,
+    Skip,
+    /// Download to `name (1).ext`, `name (2).ext`, ... instead.
+    Rename
+}
+
+/// What happens to the `.part` file, and its resume state, when a download
+/// fails or is cancelled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PartialFile {
+    /// Keep it when resuming is turned on and the data is not known to be
+    /// corrupt, remove it otherwise.
+    Resumable,
+    Keep,
+    Remove
+}
+
+#[derive(Clone, Debug)]
+pub struct DownloadOptions {
+    connection_count: usize,
+    segment_size: usize,
+    resume: bool,
+    retries: usize,
+    existing: ExistingFile,
+    filename_sources: Vec<FilenameSource>,
+    mirrors: Vec<String>,
+    rate_limiter: Option<RateLimiter>,
+    progress: Option<Progress>,
+    cancellation: Option<CancellationToken>,
+    partial_file: PartialFile,
+    #[cfg(feature = "checksum")]
+    checksums: Vec<Checksum>
+}
+
+impl DownloadOptions {
+
+    pub fn new() -> DownloadOptions {
+        DownloadOptions {
+            connection_count: 5,
+            segment_size: 500_000,
+            resume: false,
+            retries: 3,
+            existing: ExistingFile::Overwrite,
+            filename_sources: vec![FilenameSource::ContentDisposition, FilenameSource::Url],
+            mirrors: vec![],
+            rate_limiter: None,
+            progress: None,
+            cancellation: None,
+            partial_file: PartialFile::Resumable,
+            #[cfg(feature = "checksum")]
+            checksums: vec![]
+        }
+    }
+
+    pub fn set_connection_count(mut self, connection_count: usize) -> DownloadOptions {
+        self.connection_count = connection_count.max(1);
+        self
+    }
+
+    pub fn get_connection_count(&self) -> usize {
+        self.connection_count
+    }
+
+    pub fn set_segment_size(mut self, segment_size: usize) -> DownloadOptions {
+        self.segment_size = segment_size.max(1);
+        self
+    }
+
+    pub fn get_segment_size(&self) -> usize {
+        self.segment_size
+    }
+
+    /// Keeps a `<file>.part.state` sidecar with the finished segments so an
+    /// interrupted download continues where it stopped on the next call.
+    pub fn set_resume(mut self, resume: bool) -> DownloadOptions {
+        self.resume = resume;
+        self
+    }
+
+    pub fn get_resume(&self) -> bool {
+        self.resume
+    }
+
+    /// How many times a failed segment is requested again before the whole
+    /// download fails.
+    pub fn set_retries(mut self, retries: usize) -> DownloadOptions {
+        self.retries = retries;
+        self
+    }
+
+    pub fn get_retries(&self) -> usize {
+        self.retries
+    }
+
+    pub fn set_existing(mut self, existing: ExistingFile) -> DownloadOptions {
+        self.existing = existing;
+        self
+    }
+
+    pub fn get_existing(&self) -> ExistingFile {
+        self.existing
+    }
+
+    /// The order in which names are tried when downloading into a directory.
+    pub fn set_filename_sources(mut self, sources: Vec<FilenameSource>) -> DownloadOptions {
+        self.filename_sources = sources;
+        self
+    }
+
+    pub fn get_filename_sources(&self) -> &Vec<FilenameSource> {
+        &self.filename_sources
+    }
+
+    /// Adds another URL serving the same file. Segments are spread over the
+    /// connection and all mirrors that agree with it on the length, `ETag`
+    /// and `Last-Modified` of the file.
+    pub fn add_mirror(mut self, url: &str) -> DownloadOptions {
+        self.mirrors.push(url.to_string());
+        self
+    }
+
+    pub fn get_mirrors(&self) -> &Vec<String> {
+        &self.mirrors
+    }
+
+    /// Caps the combined throughput of all segments of this download. The
+    /// limiter can be shared with other downloads and adjusted while running.
+    pub fn set_rate_limiter(mut self, rate_limiter: RateLimiter) -> DownloadOptions {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    pub fn get_rate_limiter(&self) -> Option<&RateLimiter> {
+        self.rate_limiter.as_ref()
+    }
+
+    pub fn set_progress(mut self, progress: Progress) -> DownloadOptions {
+        self.progress = Some(progress);
+        self
+    }
+
+    pub fn get_progress(&self) -> Option<&Progress> {
+        self.progress.as_ref()
+    }
+
+    /// Stops the download with `Error::Cancelled` once `token` is cancelled.
+    /// All connections are closed before the download returns.
+    pub fn set_cancellation(mut self, token: CancellationToken) -> DownloadOptions {
+        self.cancellation = Some(token);
+        self
+    }
+
+    pub fn get_cancellation(&self) -> Option<&CancellationToken> {
+        self.cancellation.as_ref()
+    }
+
+    pub fn set_partial_file(mut self, partial_file: PartialFile) -> DownloadOptions {
+        self.partial_file = partial_file;
+        self
+    }
+
+    pub fn get_partial_file(&self) -> PartialFile {
+        self.partial_file
+    }
+
+    /// Expects the downloaded file to have this digest. Digests announced by
+    /// the server in `Repr-Digest`, `Digest` or `Content-MD5` are checked too.
+    #[cfg(feature = "checksum")]
+    pub fn set_checksum(mut self, checksum: Checksum) -> DownloadOptions {
+        self.checksums.push(checksum);
+        self
+    }
+
+    #[cfg(feature = "checksum")]
+    pub fn get_checksums(&self) -> &Vec<Checksum> {
+        &self.checksums
+    }
+
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,204 @@
+use std::{collections::BTreeMap, ops::Range};
+
+use crate::app::error::Error;
+#[cfg(feature = "checksum")]
+use super::checksum::{Checksum, Verifier};
+use super::sink::{RandomAccessSink, SequentialSink};
+
+pub(crate) enum Sink<'a> {
+    RandomAccess(&'a mut dyn RandomAccessSink),
+    Sequential(&'a mut dyn SequentialSink)
+}
+
+/// The sink a download is written to, together with everything that has to
+/// see the written bytes on the way.
+///
+/// Segments reach a sequential sink in order: the ones arriving early wait in
+/// memory until everything before them is written.
+pub(crate) struct Output<'a> {
+    sink: Sink<'a>,
+    /// Bytes taken by `append`, or by a sequential sink in total.
+    position: usize,
+    /// Bytes a sequential sink already has and a restarted stream repeats.
+    skip: usize,
+    early: BTreeMap<usize, Vec<u8>>,
+    #[cfg(feature = "checksum")]
+    expected: Vec<Checksum>,
+    #[cfg(feature = "checksum")]
+    verifier: Verifier
+}
+
+impl<'a> Output<'a> {
+
+    #[cfg(feature = "checksum")]
+    pub fn new(sink: Sink<'a>, expected: Vec<Checksum>) -> Output<'a> {
+        let verifier = Verifier::new(expected.clone());
+        Output { sink, position: 0, skip: 0, early: BTreeMap::new(), expected, verifier }
+    }
+
+    #[cfg(not(feature = "checksum"))]
+    pub fn new(sink: Sink<'a>) -> Output<'a> {
+        Output { sink, position: 0, skip: 0, early: BTreeMap::new() }
+    }
+
+    /// Appends bytes of a single stream download.
+    pub async fn append(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        match &mut self.sink {
+            Sink::RandomAccess(sink) => {
+                sink.write_at(self.position, bytes).await?;
+                self.position += bytes.len();
+                #[cfg(feature = "checksum")]
+                self.verifier.update(bytes);
+                Ok(())
+            },
+            Sink::Sequential(_) => {
+                let skipped = usize::min(self.skip, bytes.len());
+                self.skip -= skipped;
+                self.write_in_order(&bytes[skipped..]).await
+            },
+        }
+    }
+
+    pub async fn write_at(&mut self, offset: usize, bytes: &[u8]) -> Result<(), Error> {
+        match &mut self.sink {
+            Sink::RandomAccess(sink) => {
+                sink.write_at(offset, bytes).await?;
+                #[cfg(feature = "checksum")]
+                self.verifier.written(&mut **sink, offset, bytes.len()).await?;
+                Ok(())
+            },
+            Sink::Sequential(_) => {
+                if offset != self.position {
+                    self.early.insert(offset, bytes.to_vec());
+                    return Ok(());
+                }
+                self.write_in_order(bytes).await?;
+                while let Some(bytes) = self.early.remove(&self.position) {
+                    self.write_in_order(&bytes).await?;
+                }
+                Ok(())
+            },
+        }
+    }
+
+    async fn write_in_order(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        if let Sink::Sequential(sink) = &mut self.sink {
+            sink.write(bytes).await?;
+        }
+        self.position += bytes.len();
+        #[cfg(feature = "checksum")]
+        self.verifier.update(bytes);
+        Ok(())
+    }
+
+    /// Marks a range that is already on disk from an earlier run.
+    pub async fn existing(&mut self, range: &Range<usize>) -> Result<(), Error> {
+        #[cfg(feature = "checksum")]
+        if let Sink::RandomAccess(sink) = &mut self.sink {
+            self.verifier.written(&mut **sink, range.start, range.end - range.start + 1).await?;
+        }
+        #[cfg(not(feature = "checksum"))]
+        let _ = range;
+        Ok(())
+    }
+
+    pub async fn set_len(&mut self, length: usize) -> Result<(), Error> {
+        if let Sink::RandomAccess(sink) = &mut self.sink {
+            sink.set_len(length).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        if let Sink::RandomAccess(sink) = &mut self.sink {
+            sink.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Drops everything written so far to start over with a single stream.
+    /// A sequential sink cannot take bytes back, so the restarted stream
+    /// skips what it already has instead.
+    pub async fn reset(&mut self) -> Result<(), Error> {
+        match &mut self.sink {
+            Sink::RandomAccess(sink) => {
+                sink.set_len(0).await?;
+                self.position = 0;
+                #[cfg(feature = "checksum")]
+                {
+                    self.verifier = Verifier::new(self.expected.clone());
+                }
+            },
+            Sink::Sequential(_) => {
+                self.skip = self.position;
+                self.early.clear();
+            },
+        }
+        Ok(())
+    }
+
+    /// Cuts the output to `length`, verifies the expected checksums over it
+    /// and finishes the sink.
+    pub async fn finish(mut self, length: usize) -> Result<(), Error> {
+        match &mut self.sink {
+            Sink::RandomAccess(sink) => {
+                sink.flush().await?;
+                sink.set_len(length).await?;
+                #[cfg(feature = "checksum")]
+                self.verifier.finish(&mut **sink, length).await?;
+                sink.finish().await
+            },
+            Sink::Sequential(sink) => {
+                if self.position != length || !self.early.is_empty() {
+                    return Err(Error::IoError("the download ended before every segment was written".to_string()));
+                }
+                #[cfg(feature = "checksum")]
+                self.verifier.verify()?;
+                sink.finish().await
+            },
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Output, Sink};
+
+    #[cfg(feature = "checksum")]
+    fn output(sink: Sink<'_>) -> Output<'_> {
+        Output::new(sink, vec![])
+    }
+
+    #[cfg(not(feature = "checksum"))]
+    fn output(sink: Sink<'_>) -> Output<'_> {
+        Output::new(sink)
+    }
+
+    #[tokio::test]
+    async fn sequential_sink_gets_segments_in_order() {
+        let mut sink: Vec<u8> = vec![];
+        let mut output = output(Sink::Sequential(&mut sink));
+        output.write_at(6, b"78").await.unwrap();
+        output.write_at(3, b"456").await.unwrap();
+        output.write_at(0, b"123").await.unwrap();
+        output.finish(8).await.unwrap();
+
+        assert_eq!(sink, b"12345678");
+    }
+
+    #[tokio::test]
+    async fn restarted_stream_skips_what_was_written() {
+        let mut sink: Vec<u8> = vec![];
+        let mut output = output(Sink::Sequential(&mut sink));
+        output.write_at(0, b"123").await.unwrap();
+        output.write_at(6, b"78").await.unwrap();
+        output.reset().await.unwrap();
+        output.append(b"12345").await.unwrap();
+        output.append(b"678").await.unwrap();
+        output.finish(8).await.unwrap();
+
+        assert_eq!(sink, b"12345678");
+    }
+
+}
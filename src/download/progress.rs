@@ -0,0 +1,228 @@
+use std::{collections::VecDeque, ops::Range, path::{Path, PathBuf}, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use tokio::sync::{broadcast, watch};
+
+/// How far back transfer samples are kept to compute the current throughput.
+static THROUGHPUT_WINDOW: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProgressEvent {
+    Started { total: Option<usize> },
+    SegmentStarted { range: Range<usize> },
+    SegmentFinished { range: Range<usize> },
+    SegmentRetried { range: Range<usize>, attempt: usize, error: String },
+    Finished { downloaded: usize }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SegmentProgress {
+    pub range: Range<usize>,
+    pub downloaded: usize
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ProgressSnapshot {
+    /// The file being written, once its name is known.
+    pub file: Option<PathBuf>,
+    pub total: Option<usize>,
+    pub downloaded: usize,
+    pub segments: Vec<SegmentProgress>,
+    pub bytes_per_second: f64,
+    pub eta: Option<Duration>,
+    pub finished: bool
+}
+
+#[derive(Debug)]
+struct Tracker {
+    snapshot: ProgressSnapshot,
+    samples: VecDeque<(Instant, usize)>
+}
+
+/// Shared handle through which a download reports its progress.
+///
+/// Clone it into `DownloadOptions::set_progress` and keep a copy to follow
+/// the download: `watch()` yields the latest snapshot, `subscribe()` every
+/// event.
+#[derive(Clone, Debug)]
+pub struct Progress {
+    tracker: Arc<Mutex<Tracker>>,
+    snapshots: Arc<watch::Sender<ProgressSnapshot>>,
+    events: broadcast::Sender<ProgressEvent>
+}
+
+impl Progress {
+
+    pub fn new() -> Progress {
+        let (snapshots, _) = watch::channel(ProgressSnapshot::default());
+        let (events, _) = broadcast::channel(1024);
+        let tracker = Tracker { snapshot: ProgressSnapshot::default(), samples: VecDeque::new() };
+        Progress { tracker: Arc::new(Mutex::new(tracker)), snapshots: Arc::new(snapshots), events }
+    }
+
+    pub fn watch(&self) -> watch::Receiver<ProgressSnapshot> {
+        self.snapshots.subscribe()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ProgressEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        self.snapshots.borrow().clone()
+    }
+
+}
+
+impl Progress {
+
+    pub(crate) fn file(&self, path: &Path) {
+        self.update(|tracker| tracker.snapshot.file = Some(path.to_path_buf()));
+    }
+
+    pub(crate) fn started(&self, total: Option<usize>) {
+        self.update(|tracker| {
+            let file = tracker.snapshot.file.take();
+            tracker.snapshot = ProgressSnapshot { file, total, ..Default::default() };
+            tracker.samples.clear();
+        });
+        self.emit(ProgressEvent::Started { total });
+    }
+
+    /// Counts bytes that were already on disk before this run, e.g. the
+    /// finished segments of a resumed download.
+    pub(crate) fn skipped(&self, bytes: usize) {
+        self.update(|tracker| tracker.snapshot.downloaded += bytes);
+    }
+
+    // Segments in flight never overlap, so their start identifies them.
+
+    pub(crate) fn segment_started(&self, range: &Range<usize>) {
+        self.update(|tracker| {
+            tracker.snapshot.segments.retain(|segment| segment.range.start != range.start);
+            tracker.snapshot.segments.push(SegmentProgress { range: range.clone(), downloaded: 0 });
+        });
+        self.emit(ProgressEvent::SegmentStarted { range: range.clone() });
+    }
+
+    pub(crate) fn received(&self, segment_start: Option<usize>, bytes: usize) {
+        self.update(|tracker| {
+            tracker.snapshot.downloaded += bytes;
+            if let Some(start) = segment_start {
+                if let Some(segment) = tracker.snapshot.segments.iter_mut().find(|s| s.range.start == start) {
+                    segment.downloaded += bytes;
+                }
+            }
+        });
+    }
+
+    /// The segment starting at `segment_start` now ends at `end`, the rest
+    /// was handed to another connection.
+    pub(crate) fn segment_resized(&self, segment_start: usize, end: usize) {
+        self.update(|tracker| {
+            if let Some(segment) = tracker.snapshot.segments.iter_mut().find(|s| s.range.start == segment_start) {
+                segment.range.end = end;
+            }
+        });
+    }
+
+    /// The segment starting at `segment_start` failed; `remaining` is what
+    /// is requested again.
+    pub(crate) fn segment_retried(&self, segment_start: usize, remaining: &Range<usize>, attempt: usize, error: String) {
+        self.update(|tracker| tracker.snapshot.segments.retain(|segment| segment.range.start != segment_start));
+        self.emit(ProgressEvent::SegmentRetried { range: remaining.clone(), attempt, error });
+    }
+
+    pub(crate) fn segment_finished(&self, range: &Range<usize>) {
+        self.update(|tracker| tracker.snapshot.segments.retain(|segment| segment.range.start != range.start));
+        self.emit(ProgressEvent::SegmentFinished { range: range.clone() });
+    }
+
+    pub(crate) fn finished(&self) {
+        let mut downloaded = 0;
+        self.update(|tracker| {
+            tracker.snapshot.finished = true;
+            tracker.snapshot.eta = Some(Duration::ZERO);
+            downloaded = tracker.snapshot.downloaded;
+        });
+        self.emit(ProgressEvent::Finished { downloaded });
+    }
+
+    fn update<F: FnOnce(&mut Tracker)>(&self, f: F) {
+        let mut tracker = self.tracker.lock().unwrap();
+        f(&mut tracker);
+
+        let now = Instant::now();
+        let downloaded = tracker.snapshot.downloaded;
+        tracker.samples.push_back((now, downloaded));
+        while tracker.samples.len() > 2 && now - tracker.samples[0].0 > THROUGHPUT_WINDOW {
+            tracker.samples.pop_front();
+        }
+
+        let (since, downloaded_then) = tracker.samples[0];
+        let elapsed = (now - since).as_secs_f64();
+        if elapsed > 0.0 {
+            tracker.snapshot.bytes_per_second = downloaded.saturating_sub(downloaded_then) as f64 / elapsed;
+        }
+
+        let speed = tracker.snapshot.bytes_per_second;
+        if let (Some(total), false) = (tracker.snapshot.total, tracker.snapshot.finished) {
+            tracker.snapshot.eta = if speed > 0.0 {
+                Some(Duration::from_secs_f64(total.saturating_sub(downloaded) as f64 / speed))
+            }else {
+                None
+            };
+        }
+
+        self.snapshots.send_replace(tracker.snapshot.clone());
+    }
+
+    fn emit(&self, event: ProgressEvent) {
+        // Nobody listening is fine, progress is optional.
+        let _ = self.events.send(event);
+    }
+
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Progress, ProgressEvent};
+
+    #[test]
+    fn tracks_segments_and_totals() {
+        let progress = Progress::new();
+        let mut events = progress.subscribe();
+
+        progress.started(Some(1000));
+        progress.segment_started(&(0..999));
+        progress.received(Some(0), 200);
+
+        let snapshot = progress.snapshot();
+        assert_eq!(snapshot.downloaded, 200);
+        assert_eq!(snapshot.segments[0].downloaded, 200);
+
+        progress.segment_resized(0, 499);
+        progress.segment_started(&(500..999));
+        assert_eq!(progress.snapshot().segments[0].range, 0..499);
+
+        progress.received(Some(0), 300);
+        progress.received(Some(500), 500);
+        progress.segment_finished(&(0..499));
+        progress.segment_finished(&(500..999));
+        progress.finished();
+
+        let snapshot = progress.snapshot();
+        assert!(snapshot.finished);
+        assert_eq!(snapshot.downloaded, 1000);
+        assert!(snapshot.segments.is_empty());
+
+        assert_eq!(events.try_recv().unwrap(), ProgressEvent::Started { total: Some(1000) });
+        assert_eq!(events.try_recv().unwrap(), ProgressEvent::SegmentStarted { range: 0..999 });
+    }
+
+}
@@ -0,0 +1,448 @@
+use std::{any::Any, collections::VecDeque, ops::Range, panic, sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc, Mutex}, time::{Duration, Instant}};
+
+use tokio::{sync::mpsc::{self, Receiver, Sender}, task::JoinSet};
+use tokio_util::sync::CancellationToken;
+
+use crate::app::error::Error;
+use crate::client::{connection::{cancellable, Connection}, request::Request, response::Response, throttle::RateLimiter};
+use super::{options::DownloadOptions, output::Output, progress::Progress, state::{self, DownloadState}};
+
+/// Bytes read from a response body at once.
+pub(crate) const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Messages queued for the writer before workers wait for it, which bounds
+/// the memory a slow disk can make a download hold.
+const CHANNEL_CAPACITY: usize = 64;
+
+/// Consecutive failures after which a mirror is given up on.
+const MAX_SOURCE_FAILURES: usize = 3;
+
+/// A mirror is dropped once it is this many times slower than the fastest.
+const SLOW_SOURCE_FACTOR: f64 = 4.0;
+
+/// Segments are sized so that one takes about this long at the observed
+/// throughput of a connection.
+const SEGMENT_DURATION: Duration = Duration::from_secs(4);
+const MIN_SEGMENT_SIZE: usize = 64 * 1024;
+const MAX_SEGMENT_SIZE: usize = 64 * 1024 * 1024;
+
+/// An in-flight segment is only split when both halves get at least this.
+const MIN_STEAL_SIZE: usize = 128 * 1024;
+
+/// One server the file can be fetched from.
+pub(crate) struct Source {
+    connection: Connection,
+    validator: Option<String>,
+    failures: AtomicUsize,
+    dropped: AtomicBool,
+    active: AtomicUsize,
+    transferred: Mutex<(usize, Duration)>
+}
+
+impl Source {
+
+    pub fn new(connection: Connection, head_response: &Response) -> Source {
+        Source {
+            connection,
+            validator: state::validator_for(head_response),
+            failures: AtomicUsize::new(0),
+            dropped: AtomicBool::new(false),
+            active: AtomicUsize::new(0),
+            transferred: Mutex::new((0, Duration::ZERO))
+        }
+    }
+
+    fn is_dropped(&self) -> bool {
+        self.dropped.load(Ordering::SeqCst)
+    }
+
+    fn bytes_per_second(&self) -> Option<f64> {
+        let (bytes, elapsed) = *self.transferred.lock().unwrap();
+        if elapsed.is_zero() {
+            None
+        }else {
+            Some(bytes as f64 / elapsed.as_secs_f64())
+        }
+    }
+
+}
+
+/// A segment being downloaded. Its end shrinks when an idle worker takes
+/// over the second half of what is left.
+struct Active {
+    start: usize,
+    /// The next byte to receive and the inclusive last byte.
+    bounds: Mutex<(usize, usize)>
+}
+
+impl Active {
+
+    /// Accepts up to `length` bytes read from the response, returning the
+    /// offset to write them at and how many still belong to this segment.
+    fn advance(&self, length: usize) -> (usize, usize) {
+        let mut bounds = self.bounds.lock().unwrap();
+        let (position, end) = *bounds;
+        let accepted = usize::min(length, (end + 1).saturating_sub(position));
+        bounds.0 += accepted;
+        (position, accepted)
+    }
+
+    fn is_complete(&self) -> bool {
+        let (position, end) = *self.bounds.lock().unwrap();
+        position > end
+    }
+
+    fn remaining(&self) -> usize {
+        let (position, end) = *self.bounds.lock().unwrap();
+        (end + 1).saturating_sub(position)
+    }
+
+}
+
+/// Everything the workers of one segmented download share.
+struct Job {
+    sources: Vec<Arc<Source>>,
+    /// Ranges nobody works on yet, with the attempts already spent on them.
+    pending: Mutex<VecDeque<(Range<usize>, usize)>>,
+    active: Mutex<Vec<Arc<Active>>>,
+    segment_size: AtomicUsize,
+    content_length: usize,
+    retries: usize,
+    rate_limiter: Option<RateLimiter>,
+    progress: Progress,
+    /// Cancelled with the download, or once it is over for any reason.
+    cancellation: CancellationToken,
+    sender: Sender<Message>
+}
+
+enum Message {
+    Data { offset: usize, bytes: Vec<u8> },
+    Finished { range: Range<usize> },
+    Failed(Error)
+}
+
+impl Job {
+
+    /// Cuts the next segment from the pending ranges, or splits the in-flight
+    /// segment with the most bytes left when there are none.
+    fn next(&self) -> Option<(Arc<Active>, usize)> {
+        let next = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.pop_front().map(|(range, attempt)| {
+                let size = self.segment_size.load(Ordering::SeqCst);
+                if range.end - range.start + 1 > size {
+                    let cut = range.start + size;
+                    pending.push_front((cut..range.end, attempt));
+                    (range.start..cut - 1, attempt)
+                }else {
+                    (range, attempt)
+                }
+            })
+        };
+
+        let (range, attempt) = match next {
+            Some(v) => v,
+            None => (self.steal()?, 0),
+        };
+
+        let active = Arc::new(Active { start: range.start, bounds: Mutex::new((range.start, range.end)) });
+        self.active.lock().unwrap().push(Arc::clone(&active));
+        self.progress.segment_started(&range);
+        Some((active, attempt))
+    }
+
+    fn steal(&self) -> Option<Range<usize>> {
+        let active = self.active.lock().unwrap();
+        let victim = active.iter().max_by_key(|segment| segment.remaining())?;
+
+        let mut bounds = victim.bounds.lock().unwrap();
+        let (position, end) = *bounds;
+        let remaining = (end + 1).saturating_sub(position);
+        if remaining < 2 * MIN_STEAL_SIZE {
+            return None;
+        }
+
+        let middle = position + remaining / 2;
+        bounds.1 = middle - 1;
+        self.progress.segment_resized(victim.start, middle - 1);
+        Some(middle..end)
+    }
+
+    /// Stops tracking `segment` and returns how many bytes it received and
+    /// the range they cover.
+    fn finish(&self, segment: &Arc<Active>) -> (usize, Range<usize>) {
+        self.active.lock().unwrap().retain(|active| !Arc::ptr_eq(active, segment));
+        let (position, _) = *segment.bounds.lock().unwrap();
+        (position - segment.start, segment.start..position.saturating_sub(1))
+    }
+
+    /// Adapts the size of new segments to the throughput of the segment that
+    /// just finished, smoothing out single slow or fast ones.
+    fn adapt_segment_size(&self, bytes: usize, elapsed: Duration) {
+        if elapsed.is_zero() {
+            return;
+        }
+        let ideal = bytes as f64 / elapsed.as_secs_f64() * SEGMENT_DURATION.as_secs_f64();
+        let current = self.segment_size.load(Ordering::SeqCst) as f64;
+        let size = (current * 0.5 + ideal * 0.5) as usize;
+        self.segment_size.store(size.clamp(MIN_SEGMENT_SIZE, MAX_SEGMENT_SIZE), Ordering::SeqCst);
+    }
+
+    /// The healthy source with the fewest segments in flight.
+    fn pick_source(&self) -> Option<Arc<Source>> {
+        self.sources.iter()
+            .filter(|source| !source.is_dropped())
+            .min_by_key(|source| source.active.load(Ordering::SeqCst))
+            .cloned()
+    }
+
+    fn healthy_sources(&self) -> usize {
+        self.sources.iter().filter(|source| !source.is_dropped()).count()
+    }
+
+    fn drop_slow_sources(&self) {
+        let speeds: Vec<(&Arc<Source>, f64)> = self.sources.iter()
+            .filter(|source| !source.is_dropped())
+            .filter_map(|source| source.bytes_per_second().map(|speed| (source, speed)))
+            .collect();
+        let fastest = speeds.iter().map(|(_, speed)| *speed).fold(0.0, f64::max);
+
+        for (source, speed) in speeds {
+            if speed * SLOW_SOURCE_FACTOR < fastest && self.healthy_sources() > 1 {
+                source.dropped.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+
+}
+
+/// Fetches `content_length` bytes in parallel `Range` requests spread over
+/// `sources`. Every response must be a `206 Partial Content` whose
+/// `Content-Range` matches the requested segment. A source that does not
+/// comply, keeps failing or is much slower than the others is dropped and its
+/// segments go to the remaining ones; once none is left the last error is
+/// returned, `Error::RangeNotSupported` letting the caller fall back to a
+/// single stream.
+///
+/// Segments are not fixed: their size follows the observed throughput and a
+/// worker running out of work takes over half of the biggest segment still
+/// in flight. Ranges already listed in `state` are skipped and new ones are
+/// recorded there once written.
+///
+/// Returns only once every worker has stopped. A worker that panics stops
+/// the others and the panic is resumed here.
+pub(crate) async fn download_segments(
+    sources: Vec<Arc<Source>>,
+    output: &mut Output<'_>,
+    content_length: usize,
+    options: &DownloadOptions,
+    progress: &Progress,
+    mut state: Option<&mut DownloadState>
+) -> Result<(), Error> {
+    let (sender, mut receiver) = mpsc::channel::<Message>(CHANNEL_CAPACITY);
+
+    let completed = state.as_ref().map(|state| state.completed.clone()).unwrap_or_default();
+    let missing = state::missing(&completed, content_length);
+    let missing_length: usize = missing.iter().map(|range| range.end - range.start + 1).sum();
+
+    progress.started(Some(content_length));
+    progress.skipped(content_length - missing_length);
+    for range in &completed {
+        output.existing(range).await?;
+    }
+
+    let cancellation = options.get_cancellation().map(|token| token.child_token()).unwrap_or_default();
+    let job = Arc::new(Job {
+        sources,
+        pending: Mutex::new(missing.into_iter().map(|range| (range, 0)).collect()),
+        active: Mutex::new(vec![]),
+        segment_size: AtomicUsize::new(options.get_segment_size()),
+        content_length,
+        retries: options.get_retries(),
+        rate_limiter: options.get_rate_limiter().cloned(),
+        progress: progress.clone(),
+        cancellation: cancellation.clone(),
+        sender
+    });
+
+    let mut workers = JoinSet::new();
+    for _ in 0..options.get_connection_count() {
+        workers.spawn(worker(Arc::clone(&job)));
+    }
+    drop(job);
+
+    let mut panicked = None;
+    let result = write(&mut receiver, &mut workers, output, &mut state, &cancellation, &mut panicked).await;
+
+    // Stop whatever still runs and wait for it; closing the channel releases
+    // workers waiting for room in it.
+    cancellation.cancel();
+    receiver.close();
+    while let Some(joined) = workers.join_next().await {
+        if let Err(e) = joined {
+            if e.is_panic() && panicked.is_none() {
+                panicked = Some(e.into_panic());
+            }
+        }
+    }
+    if let Some(payload) = panicked {
+        panic::resume_unwind(payload);
+    }
+
+    result?;
+    progress.finished();
+    Ok(())
+}
+
+/// Writes what the workers send until all of them are done, one fails or
+/// the download is cancelled. A panicking worker ends it too, leaving the
+/// panic in `panicked`.
+async fn write(
+    receiver: &mut Receiver<Message>,
+    workers: &mut JoinSet<()>,
+    output: &mut Output<'_>,
+    state: &mut Option<&mut DownloadState>,
+    cancellation: &CancellationToken,
+    panicked: &mut Option<Box<dyn Any + Send>>
+) -> Result<(), Error> {
+    loop {
+        tokio::select! {
+            biased;
+            _ = cancellation.cancelled() => return Err(Error::Cancelled),
+            Some(Err(e)) = workers.join_next(), if !workers.is_empty() => {
+                if e.is_panic() {
+                    *panicked = Some(e.into_panic());
+                    return Ok(());
+                }
+            },
+            message = receiver.recv() => match message {
+                Some(Message::Data { offset, bytes }) => {
+                    output.write_at(offset, &bytes).await?;
+                },
+                Some(Message::Finished { range }) => {
+                    if let Some(state) = state.as_mut() {
+                        output.flush().await?;
+                        state.mark_completed(range).await?;
+                    }
+                },
+                Some(Message::Failed(e)) => return Err(e),
+                None => return Ok(()),
+            },
+        }
+    }
+}
+
+async fn worker(job: Arc<Job>) {
+    while let Some((segment, attempt)) = job.next() {
+        let source = match job.pick_source() {
+            Some(source) => source,
+            None => return,
+        };
+
+        source.active.fetch_add(1, Ordering::SeqCst);
+        let started = Instant::now();
+        let result = cancellable(Some(&job.cancellation), stream_segment(&job, &source, &segment)).await;
+        source.active.fetch_sub(1, Ordering::SeqCst);
+
+        let (received, range) = job.finish(&segment);
+        if received > 0 {
+            // Whatever arrived is written, even if the rest failed.
+            job.progress.segment_finished(&range);
+            if job.sender.send(Message::Finished { range }).await.is_err() {
+                return;
+            }
+        }
+
+        match result {
+            Ok(()) => {
+                source.failures.store(0, Ordering::SeqCst);
+                let mut transferred = source.transferred.lock().unwrap();
+                transferred.0 += received;
+                transferred.1 += started.elapsed();
+                drop(transferred);
+
+                job.adapt_segment_size(received, started.elapsed());
+                job.drop_slow_sources();
+            },
+            Err(Error::Cancelled) => return,
+            Err(e) => {
+                // A source that ignores ranges or serves another file is of
+                // no use for any segment.
+                let unusable = matches!(e, Error::RangeNotSupported | Error::ResourceChanged);
+                let failures = source.failures.fetch_add(1, Ordering::SeqCst) + 1;
+                if unusable || (failures >= MAX_SOURCE_FAILURES && job.healthy_sources() > 1) {
+                    source.dropped.store(true, Ordering::SeqCst);
+                }
+
+                let attempt = if unusable { attempt } else { attempt + 1 };
+                if job.healthy_sources() == 0 || attempt > job.retries {
+                    let _ = job.sender.send(Message::Failed(e)).await;
+                    return;
+                }
+
+                let (position, end) = *segment.bounds.lock().unwrap();
+                job.progress.segment_retried(segment.start, &(position..end), attempt, e.to_string());
+                job.pending.lock().unwrap().push_front((position..end, attempt));
+            },
+        }
+    }
+}
+
+async fn stream_segment(job: &Job, source: &Source, segment: &Active) -> Result<(), Error> {
+    let range = {
+        let (position, end) = *segment.bounds.lock().unwrap();
+        position..end
+    };
+    let mut request = Request::new().set_range(range.clone());
+    if let Some(validator) = &source.validator {
+        request = request.add_header("If-Range", validator);
+    }
+    let (response, mut stream) = source.connection.open(request).await?;
+
+    // A full response to an `If-Range` request means the file changed, unless
+    // it is still the same file and the server just ignores ranges.
+    if response.status_code == 200 && source.validator.is_some() && state::validator_for(&response) != source.validator {
+        return Err(Error::ResourceChanged);
+    }
+
+    if response.status_code != 206 {
+        return Err(Error::RangeNotSupported);
+    }
+
+    match response.content_range() {
+        Some((served, total)) if served == range && total.unwrap_or(job.content_length) == job.content_length => {},
+        _ => return Err(Error::RangeNotSupported),
+    }
+
+    let mut body = response.body_reader(&mut stream);
+    let mut chunk = vec![0u8; CHUNK_SIZE];
+
+    while !segment.is_complete() {
+        let read = body.read(&mut chunk).await?;
+        if read == 0 {
+            return Err(Error::IoError("the connection closed before the segment was complete".to_string()));
+        }
+
+        // Bytes past a shrunk end belong to another worker now.
+        let (offset, accepted) = segment.advance(read);
+        if accepted > 0 {
+            if job.sender.send(Message::Data { offset, bytes: chunk[..accepted].to_vec() }).await.is_err() {
+                // The writer gave up on the download, nothing left to do.
+                return Ok(());
+            }
+            job.progress.received(Some(segment.start), accepted);
+        }
+        throttle(&source.connection, job.rate_limiter.as_ref(), read).await;
+    }
+
+    Ok(())
+}
+
+/// Waits until `bytes` fit into both the client wide and the per download
+/// rate limit.
+pub(crate) async fn throttle(connection: &Connection, rate_limiter: Option<&RateLimiter>, bytes: usize) {
+    connection.client.throttle(bytes).await;
+    if let Some(rate_limiter) = rate_limiter {
+        rate_limiter.acquire(bytes).await;
+    }
+}
@@ -0,0 +1,265 @@
+use async_trait::async_trait;
+use tokio::{fs::File, io::{AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt}};
+
+use crate::app::error::Error;
+
+/// A destination bytes can be written to at any offset, so every segment of
+/// a download goes straight to where it belongs.
+#[async_trait]
+pub trait RandomAccessSink: Send {
+
+    async fn write_at(&mut self, offset: usize, bytes: &[u8]) -> Result<(), Error>;
+
+    /// Reads back bytes written earlier, which is how checksums are computed
+    /// over segments arriving out of order.
+    async fn read_at(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), Error>;
+
+    /// Grows or cuts the sink to `length` bytes.
+    async fn set_len(&mut self, length: usize) -> Result<(), Error>;
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called once after the last byte is written.
+    async fn finish(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+}
+
+/// A destination that only takes bytes in order, like stdout or a pipe. The
+/// segments of a download are put back in order before they reach it.
+#[async_trait]
+pub trait SequentialSink: Send {
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), Error>;
+
+    /// Called once after the last byte is written.
+    async fn finish(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+}
+
+#[async_trait]
+impl RandomAccessSink for File {
+
+    async fn write_at(&mut self, offset: usize, bytes: &[u8]) -> Result<(), Error> {
+        self.seek(std::io::SeekFrom::Start(offset as u64)).await?;
+        self.write_all(bytes).await?;
+        Ok(())
+    }
+
+    async fn read_at(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), Error> {
+        AsyncWriteExt::flush(self).await?;
+        self.seek(std::io::SeekFrom::Start(offset as u64)).await?;
+        self.read_exact(buffer).await?;
+        Ok(())
+    }
+
+    async fn set_len(&mut self, length: usize) -> Result<(), Error> {
+        File::set_len(self, length as u64).await?;
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        AsyncWriteExt::flush(self).await?;
+        Ok(())
+    }
+
+    /// Makes sure everything reached the disk.
+    async fn finish(&mut self) -> Result<(), Error> {
+        AsyncWriteExt::flush(self).await?;
+        self.sync_all().await?;
+        Ok(())
+    }
+
+}
+
+#[async_trait]
+impl RandomAccessSink for Vec<u8> {
+
+    async fn write_at(&mut self, offset: usize, bytes: &[u8]) -> Result<(), Error> {
+        let end = offset + bytes.len();
+        if end > self.len() {
+            self.resize(end, 0);
+        }
+        self[offset..end].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    async fn read_at(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), Error> {
+        let bytes = self.get(offset..offset + buffer.len())
+            .ok_or_else(|| Error::IoError("read past the end of the buffer".to_string()))?;
+        buffer.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    async fn set_len(&mut self, length: usize) -> Result<(), Error> {
+        self.resize(length, 0);
+        Ok(())
+    }
+
+}
+
+#[async_trait]
+impl SequentialSink for Vec<u8> {
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+
+}
+
+/// Feeds a download into any `AsyncWrite`, e.g. `tokio::io::stdout()`, the
+/// stdin of a child process or one end of a `tokio::io::duplex` pipe.
+pub struct Writer<W> {
+    writer: W
+}
+
+impl<W> Writer<W> {
+
+    pub fn new(writer: W) -> Writer<W> {
+        Writer { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+}
+
+#[async_trait]
+impl<W: AsyncWrite + Unpin + Send> SequentialSink for Writer<W> {
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.writer.write_all(bytes).await?;
+        Ok(())
+    }
+
+    /// Shuts the writer down, which closes pipes so the reader sees the end.
+    async fn finish(&mut self) -> Result<(), Error> {
+        self.writer.shutdown().await?;
+        Ok(())
+    }
+
+}
+
+/// A file written through a memory map instead of system calls.
+#[cfg(feature = "mmap")]
+pub struct MmapSink {
+    file: std::fs::File,
+    map: Option<memmap2::MmapMut>
+}
+
+#[cfg(feature = "mmap")]
+impl MmapSink {
+
+    pub fn new(file: std::fs::File) -> Result<MmapSink, Error> {
+        let mut sink = MmapSink { file, map: None };
+        sink.remap()?;
+        Ok(sink)
+    }
+
+    fn remap(&mut self) -> Result<(), Error> {
+        self.map = None;
+        if self.file.metadata()?.len() > 0 {
+            // SAFETY: the file is owned by the sink and only ever changes
+            // through the map or `set_len`, which drops the map first. Other
+            // processes modifying the file are not guarded against.
+            self.map = Some(unsafe { memmap2::MmapMut::map_mut(&self.file)? });
+        }
+        Ok(())
+    }
+
+    fn range(&mut self, offset: usize, length: usize) -> Result<&mut [u8], Error> {
+        self.map.as_mut()
+            .and_then(|map| map.get_mut(offset..offset + length))
+            .ok_or_else(|| Error::IoError("access past the end of the mapped file".to_string()))
+    }
+
+    async fn sync(&mut self) -> Result<(), Error> {
+        if let Some(map) = self.map.take() {
+            // Flushing a map blocks until the pages are on disk.
+            let map = tokio::task::spawn_blocking(move || map.flush().map(|_| map)).await
+                .map_err(|e| Error::IoError(e.to_string()))??;
+            self.map = Some(map);
+        }
+        Ok(())
+    }
+
+}
+
+#[cfg(feature = "mmap")]
+#[async_trait]
+impl RandomAccessSink for MmapSink {
+
+    async fn write_at(&mut self, offset: usize, bytes: &[u8]) -> Result<(), Error> {
+        // Growing remaps the file, so it grows in big steps when the length
+        // is not known up front. `finish` of the download cuts it to size.
+        let length = self.map.as_ref().map(|map| map.len()).unwrap_or(0);
+        if offset + bytes.len() > length {
+            self.set_len(usize::max(offset + bytes.len(), length * 2)).await?;
+        }
+        self.range(offset, bytes.len())?.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    async fn read_at(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), Error> {
+        buffer.copy_from_slice(self.range(offset, buffer.len())?);
+        Ok(())
+    }
+
+    async fn set_len(&mut self, length: usize) -> Result<(), Error> {
+        self.sync().await?;
+        self.map = None;
+        self.file.set_len(length as u64)?;
+        self.remap()
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        if let Some(map) = &self.map {
+            map.flush_async()?;
+        }
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> Result<(), Error> {
+        self.sync().await
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RandomAccessSink, SequentialSink, Writer};
+
+    #[tokio::test]
+    async fn memory_sink_grows_and_reads_back() {
+        let mut sink: Vec<u8> = vec![];
+        RandomAccessSink::write_at(&mut sink, 4, b"5678").await.unwrap();
+        RandomAccessSink::write_at(&mut sink, 0, b"1234").await.unwrap();
+
+        let mut buffer = [0u8; 3];
+        sink.read_at(3, &mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"456");
+        assert!(sink.read_at(6, &mut buffer).await.is_err());
+        assert_eq!(sink, b"12345678");
+    }
+
+    #[tokio::test]
+    async fn writer_closes_the_pipe() {
+        let (mut reader, writer) = tokio::io::duplex(64);
+        let mut sink = Writer::new(writer);
+        sink.write(b"hello").await.unwrap();
+        sink.finish().await.unwrap();
+        drop(sink);
+
+        let mut received = vec![];
+        tokio::io::AsyncReadExt::read_to_end(&mut reader, &mut received).await.unwrap();
+        assert_eq!(received, b"hello");
+    }
+
+}
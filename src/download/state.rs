@@ -0,0 +1,223 @@
+use std::{ops::Range, path::{Path, PathBuf}};
+
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+use crate::app::error::Error;
+use crate::client::response::Response;
+
+static HEADER: &str = "http_client-state 1";
+
+/// Progress of a segmented download, kept next to the target file so an
+/// interrupted download can continue instead of starting over.
+///
+/// The file is line based and append only: a header with the length and
+/// validators of the remote file, followed by one `done <start> <end>` line
+/// per finished segment.
+#[derive(Debug, PartialEq)]
+pub struct DownloadState {
+    path: PathBuf,
+    pub content_length: usize,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub completed: Vec<Range<usize>>
+}
+
+impl DownloadState {
+
+    pub fn sidecar_path(file_path: &Path) -> PathBuf {
+        let mut name = file_path.file_name().unwrap_or_default().to_os_string();
+        name.push(".state");
+        file_path.with_file_name(name)
+    }
+
+    pub async fn load(file_path: &Path) -> Result<Option<DownloadState>, Error> {
+        let path = Self::sidecar_path(file_path);
+        if !path.exists() || !file_path.exists() {
+            return Ok(None);
+        }
+
+        let content = tokio::fs::read_to_string(&path).await?;
+        Ok(Self::parse(path, &content))
+    }
+
+    pub async fn create(file_path: &Path, content_length: usize, response: &Response) -> Result<DownloadState, Error> {
+        let state = DownloadState {
+            path: Self::sidecar_path(file_path),
+            content_length,
+            etag: response.header("ETag").map(str::to_string),
+            last_modified: response.header("Last-Modified").map(str::to_string),
+            completed: vec![]
+        };
+
+        let mut content = format!("{}\nlength {}\n", HEADER, content_length);
+        if let Some(etag) = &state.etag {
+            content.push_str(&format!("etag {}\n", etag));
+        }
+        if let Some(last_modified) = &state.last_modified {
+            content.push_str(&format!("last-modified {}\n", last_modified));
+        }
+        tokio::fs::write(&state.path, content).await?;
+
+        Ok(state)
+    }
+
+    fn parse(path: PathBuf, content: &str) -> Option<DownloadState> {
+        let mut lines = content.split_inclusive('\n');
+        if lines.next()?.trim_end() != HEADER {
+            return None;
+        }
+
+        let mut state = DownloadState { path, content_length: 0, etag: None, last_modified: None, completed: vec![] };
+        for line in lines {
+            // A torn last line from an interrupted write is ignored.
+            let line = match line.strip_suffix('\n') {
+                Some(line) => line,
+                None => break,
+            };
+            let (key, value) = match line.split_once(' ') {
+                Some(v) => v,
+                None => continue,
+            };
+            match key {
+                "length" => state.content_length = value.parse().ok()?,
+                "etag" => state.etag = Some(value.to_string()),
+                "last-modified" => state.last_modified = Some(value.to_string()),
+                "done" => {
+                    if let Some((start, end)) = value.split_once(' ') {
+                        if let (Ok(start), Ok(end)) = (start.parse(), end.parse()) {
+                            state.completed.push(start..end);
+                        }
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        Some(state)
+    }
+
+    /// The value to send in `If-Range`.
+    pub fn validator(&self) -> Option<&str> {
+        validator(self.etag.as_deref(), self.last_modified.as_deref())
+    }
+
+    /// Whether the remote file described by `response` is still the one this
+    /// state was recorded for.
+    pub fn matches(&self, content_length: usize, response: &Response) -> bool {
+        self.validator().is_some()
+            && self.content_length == content_length
+            && self.etag.as_deref() == response.header("ETag")
+            && self.last_modified.as_deref() == response.header("Last-Modified")
+    }
+
+    /// The inclusive ranges of the file that are not completed yet.
+    pub fn missing(&self, content_length: usize) -> Vec<Range<usize>> {
+        missing(&self.completed, content_length)
+    }
+
+    pub async fn mark_completed(&mut self, range: Range<usize>) -> Result<(), Error> {
+        let mut file = OpenOptions::new().append(true).open(&self.path).await?;
+        file.write_all(format!("done {} {}\n", range.start, range.end).as_bytes()).await?;
+        file.flush().await?;
+        self.completed.push(range);
+        Ok(())
+    }
+
+    pub async fn remove(self) -> Result<(), Error> {
+        match tokio::fs::remove_file(&self.path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn discard(file_path: &Path) -> Result<(), Error> {
+        match tokio::fs::remove_file(Self::sidecar_path(file_path)).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+}
+
+/// The inclusive ranges of a `content_length` bytes long file not covered by
+/// `completed`.
+pub fn missing(completed: &[Range<usize>], content_length: usize) -> Vec<Range<usize>> {
+    let mut completed = completed.to_vec();
+    completed.sort_by_key(|range| range.start);
+
+    let mut missing = vec![];
+    let mut position = 0;
+    for range in completed {
+        if range.start > position {
+            missing.push(position..range.start - 1);
+        }
+        position = usize::max(position, range.end + 1);
+    }
+    if position < content_length {
+        missing.push(position..content_length - 1);
+    }
+    missing
+}
+
+/// The `If-Range` value for the file described by `response`.
+pub fn validator_for(response: &Response) -> Option<String> {
+    validator(response.header("ETag"), response.header("Last-Modified")).map(str::to_string)
+}
+
+/// Weak entity tags are not allowed in `If-Range`, so `Last-Modified` is
+/// used for them instead.
+fn validator<'a>(etag: Option<&'a str>, last_modified: Option<&'a str>) -> Option<&'a str> {
+    match etag {
+        Some(etag) if !etag.starts_with("W/") => Some(etag),
+        _ => last_modified,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use super::DownloadState;
+
+    #[test]
+    fn sidecar_is_next_to_file() {
+        let path = DownloadState::sidecar_path(Path::new("/tmp/archive.tar.gz"));
+        assert_eq!(path, PathBuf::from("/tmp/archive.tar.gz.state"));
+    }
+
+    #[test]
+    fn parse_state() {
+        let content = "http_client-state 1\nlength 1000\netag \"abc\"\ndone 0 499\ndone 500 9";
+        let state = DownloadState::parse(PathBuf::from("x.state"), content).unwrap();
+
+        assert_eq!(state.content_length, 1000);
+        assert_eq!(state.validator(), Some("\"abc\""));
+        assert_eq!(state.completed, vec![0..499]);
+    }
+
+    #[test]
+    fn missing_ranges() {
+        let content = "http_client-state 1\nlength 1000\ndone 100 199\ndone 0 49\ndone 150 299\n";
+        let state = DownloadState::parse(PathBuf::from("x.state"), content).unwrap();
+        assert_eq!(state.missing(1000), vec![50..99, 300..999]);
+
+        let content = "http_client-state 1\nlength 10\ndone 0 9\n";
+        let state = DownloadState::parse(PathBuf::from("x.state"), content).unwrap();
+        assert!(state.missing(10).is_empty());
+    }
+
+    #[test]
+    fn weak_etag_is_not_a_validator() {
+        let content = "http_client-state 1\nlength 10\netag W/\"abc\"\n";
+        let state = DownloadState::parse(PathBuf::from("x.state"), content).unwrap();
+        assert_eq!(state.validator(), None);
+    }
+
+    #[test]
+    fn unknown_format_is_ignored() {
+        assert!(DownloadState::parse(PathBuf::from("x.state"), "garbage").is_none());
+    }
+
+}
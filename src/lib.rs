@@ -0,0 +1,28 @@
+//! An asynchronous HTTP/1.1 client on tokio and rustls, with a downloader
+//! that fetches files over several connections, resumes them and verifies
+//! their checksums.
+//!
+//! ```no_run
+//! use http_client::prelude::*;
+//!
+//! # async fn example() -> Result<(), Error> {
+//! let mut connection = Connection::new("https://example.com/index.html").await?;
+//! let response = connection.request(Request::new().set_method(Method::GET)).await?;
+//! println!("{} {}", response.status_code, response.status_name);
+//!
+//! let mut connection = Connection::new("https://example.com/file.zip").await?;
+//! let path = connection.download(std::path::Path::new(".")).await?;
+//! println!("saved to {}", path.display());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The most used types are re-exported from [`client`], [`download`] and
+//! the crate root; `use http_client::prelude::*` brings them all in.
+
+pub mod app;
+pub mod client;
+pub mod download;
+pub mod prelude;
+
+pub use app::error::Error;
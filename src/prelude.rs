@@ -0,0 +1,9 @@
+//! Everything needed for everyday requests and downloads.
+//!
+//! ```
+//! use http_client::prelude::*;
+//! ```
+
+pub use crate::client::{Client, Connection, CookieJar, Method, ParsedUrl, Request, Response};
+pub use crate::download::{DownloadOptions, Progress};
+pub use crate::Error;